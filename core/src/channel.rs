@@ -6,12 +6,15 @@ use std::{
 use crate::{
     helpers::{prepapre_cache_vec, sum_simd},
     voice::VoiceControlData,
-    AudioStreamParams, SingleBorrowRefCell,
+    AudioStreamParams, SingleBorrowRef, SingleBorrowRefCell,
 };
 
 use self::{
+    channel_sf::{ChannelSoundfont, PERCUSSION_BANK},
     key::KeyData,
-    params::{VoiceChannelConst, VoiceChannelParams, VoiceChannelStatsReader},
+    mixer::ChannelMixer,
+    note_router::NoteRouter,
+    params::{VoiceChannelConst, VoiceChannelParams, VoiceChannelStatsReader, VoiceSpawnParams},
 };
 
 use super::{soundfont::SoundfontBase, AudioPipe};
@@ -22,13 +25,22 @@ use to_vec::ToVec;
 
 mod channel_sf;
 mod key;
+mod note_router;
 mod params;
+
+mod mixer;
+pub use mixer::PanLaw;
+mod steal_policy;
+pub use steal_policy::*;
 mod voice_buffer;
 mod voice_spawner;
 
 mod event;
 pub use event::*;
 
+mod sysex;
+pub use sysex::*;
+
 #[derive(Clone)]
 pub struct VoiceChannel {
     data: Arc<Mutex<VoiceChannelData>>,
@@ -39,13 +51,20 @@ pub struct VoiceChannel {
 struct Key {
     data: SingleBorrowRefCell<KeyData>,
     audio_cache: SingleBorrowRefCell<Vec<f32>>,
-    event_cache: SingleBorrowRefCell<Vec<NoteEvent>>,
+    /// The key events for the next render, with their frame offsets into it
+    event_cache: SingleBorrowRefCell<Vec<(u32, NoteEvent)>>,
 }
 
 impl Key {
-    pub fn new(key: u8, shared_voice_counter: Arc<AtomicU64>) -> Self {
+    pub fn new(
+        key: u8,
+        shared_voice_counter: Arc<AtomicU64>,
+        stream_params: AudioStreamParams,
+        params: Arc<VoiceSpawnParams>,
+    ) -> Self {
+        let data = KeyData::new(key, shared_voice_counter, stream_params, params);
         Key {
-            data: SingleBorrowRefCell::new(KeyData::new(key, shared_voice_counter)),
+            data: SingleBorrowRefCell::new(data),
             audio_cache: SingleBorrowRefCell::new(Vec::new()),
             event_cache: SingleBorrowRefCell::new(Vec::new()),
         }
@@ -59,6 +78,21 @@ struct ControlEventData {
     pitch_bend_sensitivity_msb: u8,
     pitch_bend_sensitivity: f32,
     pitch_bend_value: f32,
+    /// The pitch bend, in semitones
+    pitch_bend: f32,
+    fine_tune_lsb: u8,
+    fine_tune_msb: u8,
+    /// The channel fine tuning, in cents
+    fine_tune: f32,
+    /// The channel coarse tuning, in semitones
+    coarse_tune: f32,
+    bank_msb: u8,
+    bank_lsb: u8,
+    program: u8,
+    percussion: bool,
+    portamento: bool,
+    /// The portamento glide speed, in semitones per sample
+    portamento_rate: f32,
 }
 
 impl ControlEventData {
@@ -70,6 +104,17 @@ impl ControlEventData {
             pitch_bend_sensitivity_msb: 2,
             pitch_bend_sensitivity: 2.0,
             pitch_bend_value: 0.0,
+            pitch_bend: 0.0,
+            fine_tune_lsb: 0,
+            fine_tune_msb: 64,
+            fine_tune: 0.0,
+            coarse_tune: 0.0,
+            bank_msb: 0,
+            bank_lsb: 0,
+            program: 0,
+            percussion: false,
+            portamento: false,
+            portamento_rate: f32::INFINITY,
         }
     }
 }
@@ -85,6 +130,21 @@ struct VoiceChannelData {
 
     /// Processed control data, ready to feed to voices
     voice_control_data: AtomicRefCell<VoiceControlData>,
+
+    /// Picks the voice spawners of the selected preset from the soundfonts
+    channel_sf: RefCell<ChannelSoundfont>,
+    /// The parameters that keys spawn voices with, as of the last pushed event
+    spawn_params: RefCell<VoiceSpawnParams>,
+
+    /// Turns note events into key events, for mono mode and portamento
+    note_router: RefCell<NoteRouter>,
+
+    /// Applies the channel volume and pan to the rendered audio
+    mixer: ChannelMixer,
+    /// The control data used by the mixer at the current position
+    mixer_control: Arc<VoiceControlData>,
+    /// The control data changes for the next render, with their frame offsets into it
+    mixer_events: RefCell<Vec<(u32, Arc<VoiceControlData>)>>,
 }
 
 impl VoiceChannelData {
@@ -104,39 +164,61 @@ impl VoiceChannelData {
         let params = VoiceChannelParams::new(sample_rate, channels);
         let shared_voice_counter = params.stats.voice_counter.clone();
 
+        let channel_sf = ChannelSoundfont::new();
+        let spawn_params = VoiceSpawnParams::new(channel_sf.matrix());
+        let key_params = Arc::new(spawn_params.clone());
+
         VoiceChannelData {
             params: Arc::new(RwLock::new(params)),
             key_voices: Arc::new(fill_key_array(|i| {
-                Key::new(i, shared_voice_counter.clone())
+                Key::new(
+                    i,
+                    shared_voice_counter.clone(),
+                    AudioStreamParams::new(sample_rate, channels),
+                    key_params.clone(),
+                )
             })),
 
             threadpool,
 
             control_event_data: RefCell::new(ControlEventData::new_defaults()),
             voice_control_data: AtomicRefCell::new(VoiceControlData::new_defaults()),
+
+            channel_sf: RefCell::new(channel_sf),
+            spawn_params: RefCell::new(spawn_params),
+
+            note_router: RefCell::new(NoteRouter::new()),
+
+            mixer: ChannelMixer::new(sample_rate, channels),
+            mixer_control: Arc::new(VoiceControlData::new_defaults()),
+            mixer_events: RefCell::new(Vec::new()),
         }
     }
 
     fn push_key_events_and_render(&mut self, out: &mut [f32]) {
-        fn render_for_key(
-            key: &Key,
-            len: usize,
-            control: &VoiceControlData,
-            params: &Arc<RwLock<VoiceChannelParams>>,
-        ) {
+        fn render_for_key(key: &Key, len: usize, params: &Arc<RwLock<VoiceChannelParams>>) {
             let mut events = key.event_cache.borrow();
 
             let mut audio_cache = key.audio_cache.borrow();
             let mut data = key.data.borrow();
 
             let params = params.read().unwrap();
-            for e in events.drain(..) {
-                data.send_event(e, control, &params.channel_sf, params.layers);
-            }
+            let channels = params.constant.stream_params.channels as usize;
 
             prepapre_cache_vec(&mut audio_cache, len, 0.0);
 
-            data.render_to(&mut audio_cache);
+            // Render up to each event, so that it lands on its exact frame
+            let mut position = 0;
+            for (offset, e) in take_block_events(&mut events, len / channels) {
+                let offset = offset as usize * channels;
+                if offset > position {
+                    data.render_to(&mut audio_cache[position..offset]);
+                    position = offset;
+                }
+                data.send_event(e);
+            }
+
+            data.render_to(&mut audio_cache[position..]);
         }
 
         out.fill(0.0);
@@ -145,90 +227,359 @@ impl VoiceChannelData {
                 let len = out.len();
                 let params = self.params.clone();
                 let key_voices = self.key_voices.clone();
-                let control = self.voice_control_data.borrow();
                 pool.install(|| {
                     let params = params.clone();
                     key_voices.par_iter().for_each(move |key| {
-                        render_for_key(key, len, &control, &params);
+                        render_for_key(key, len, &params);
                     });
                 });
 
                 for key in self.key_voices.iter() {
                     let key = &key.audio_cache.borrow();
-                    sum_simd(&key, out);
+                    sum_simd(key, out);
                 }
             }
             None => {
                 let len = out.len();
 
-                let control = self.voice_control_data.borrow();
                 for key in self.key_voices.iter() {
-                    render_for_key(key, len, &control, &self.params);
+                    render_for_key(key, len, &self.params);
                 }
 
                 for key in self.key_voices.iter() {
                     let key = &key.audio_cache.borrow();
-                    sum_simd(&key, out);
+                    sum_simd(key, out);
                 }
             }
         }
+
+        let channels = self.params.read().unwrap().constant.stream_params.channels as usize;
+
+        let mut events = self.mixer_events.borrow_mut();
+        let mut position = 0;
+        for (offset, control) in take_block_events(&mut events, out.len() / channels) {
+            let offset = offset as usize * channels;
+            if offset > position {
+                self.mixer
+                    .apply(&mut out[position..offset], &self.mixer_control);
+                position = offset;
+            }
+            self.mixer_control = control;
+        }
+        self.mixer.apply(&mut out[position..], &self.mixer_control);
     }
 
-    fn propagate_voice_controls(&self) {
-        let controls = self.voice_control_data.borrow();
-        for key in self.key_voices.iter() {
-            let mut data = key.data.borrow();
-            data.process_controls(&controls);
+    pub fn set_soundfonts(
+        &self,
+        soundfonts: Vec<Arc<dyn SoundfontBase>>,
+        key_events: &mut [SingleBorrowRef<Vec<(u32, NoteEvent)>>],
+        offset: u32,
+    ) {
+        self.channel_sf.borrow_mut().set_soundfonts(soundfonts);
+        self.update_spawners(key_events, offset);
+    }
+
+    /// Switches the keys to the voice spawners of the selected preset, if they changed
+    fn update_spawners(
+        &self,
+        key_events: &mut [SingleBorrowRef<Vec<(u32, NoteEvent)>>],
+        offset: u32,
+    ) {
+        let spawners = self.channel_sf.borrow().matrix();
+        if !Arc::ptr_eq(&self.spawn_params.borrow().spawners, &spawners) {
+            self.spawn_params.borrow_mut().spawners = spawners;
+            self.push_spawn_snapshot(key_events, offset);
+        }
+    }
+
+    /// Sends a snapshot of the spawn parameters to each key, so that a preset or
+    /// layer limit change applies to the notes after it, even within a render
+    fn push_spawn_snapshot(
+        &self,
+        key_events: &mut [SingleBorrowRef<Vec<(u32, NoteEvent)>>],
+        offset: u32,
+    ) {
+        let params = Arc::new(self.spawn_params.borrow().clone());
+        push_to_all_keys(key_events, offset, NoteEvent::SpawnParams(params));
+    }
+
+    /// Sends a snapshot of the voice control data to each key and the mixer,
+    /// so that a change lands on the same frame everywhere
+    fn push_control_snapshot(
+        &self,
+        key_events: &mut [SingleBorrowRef<Vec<(u32, NoteEvent)>>],
+        offset: u32,
+    ) {
+        let control = Arc::new(self.voice_control_data.borrow().clone());
+        for events in key_events.iter_mut() {
+            events.push((offset, NoteEvent::Control(control.clone())));
+        }
+        self.mixer_events.borrow_mut().push((offset, control));
+    }
+
+    /// Resets the controllers to their defaults, as described by the MIDI "Reset All
+    /// Controllers" recommended practice (RP-015). The pitch bend, portamento pedal
+    /// and RPN selection are reset, while the volume, pan, selected preset and the
+    /// values set through RPNs (pitch bend range, fine and coarse tuning) are kept.
+    pub fn reset_controllers(&self) {
+        {
+            let mut data = self.control_event_data.borrow_mut();
+            *data = ControlEventData {
+                pitch_bend_sensitivity_lsb: data.pitch_bend_sensitivity_lsb,
+                pitch_bend_sensitivity_msb: data.pitch_bend_sensitivity_msb,
+                pitch_bend_sensitivity: data.pitch_bend_sensitivity,
+                fine_tune_lsb: data.fine_tune_lsb,
+                fine_tune_msb: data.fine_tune_msb,
+                fine_tune: data.fine_tune,
+                coarse_tune: data.coarse_tune,
+                bank_msb: data.bank_msb,
+                bank_lsb: data.bank_lsb,
+                program: data.program,
+                percussion: data.percussion,
+                portamento_rate: data.portamento_rate,
+                ..ControlEventData::new_defaults()
+            };
         }
+
+        {
+            let mut control = self.voice_control_data.borrow_mut();
+            control.expression = 1.0;
+            control.modulation = 0.0;
+            control.channel_pressure = 0.0;
+        }
+        self.update_pitch_multiplier();
+        self.update_portamento();
+    }
+
+    fn note_on(
+        &self,
+        key_events: &mut [SingleBorrowRef<Vec<(u32, NoteEvent)>>],
+        offset: u32,
+        key: u8,
+        vel: u8,
+    ) {
+        let (portamento, rate) = {
+            let data = self.control_event_data.borrow();
+            (data.portamento, data.portamento_rate)
+        };
+
+        // Notes of a choke group stop the voices of that group on every key, before they play
+        let spawn_params = self.spawn_params.borrow();
+        self.note_router
+            .borrow_mut()
+            .note_on(key, vel, portamento, rate, |key, ev| {
+                if let NoteEvent::On(vel) = ev {
+                    for group in spawn_params.spawners.choke_groups_at(key, vel) {
+                        push_to_all_keys(key_events, offset, NoteEvent::Choke(*group));
+                    }
+                }
+                key_events[key as usize].push((offset, ev))
+            });
+    }
+
+    fn note_off(
+        &self,
+        key_events: &mut [SingleBorrowRef<Vec<(u32, NoteEvent)>>],
+        offset: u32,
+        key: u8,
+    ) {
+        self.note_router
+            .borrow_mut()
+            .note_off(key, |key, ev| key_events[key as usize].push((offset, ev)));
+    }
+
+    /// Switches between mono mode (CC126) and poly mode (CC127), releasing every note
+    fn set_mono(
+        &self,
+        mono: bool,
+        key_events: &mut [SingleBorrowRef<Vec<(u32, NoteEvent)>>],
+        offset: u32,
+    ) {
+        self.note_router.borrow_mut().set_mono(mono);
+        self.voice_control_data.borrow_mut().mono = mono;
+        push_to_all_keys(key_events, offset, NoteEvent::AllNotesOff);
+        self.push_control_snapshot(key_events, offset);
+    }
+
+    /// Switches to a program in the bank selected by the last CC0 and CC32 events.
+    /// The bank is the CC0 value (GS), or the CC32 value if CC0 is 0 (XG).
+    /// In percussion mode, the program selects a drum kit instead.
+    pub fn program_change(
+        &self,
+        program: u8,
+        key_events: &mut [SingleBorrowRef<Vec<(u32, NoteEvent)>>],
+        offset: u32,
+    ) {
+        let program = program.min(127);
+        let bank = {
+            let mut data = self.control_event_data.borrow_mut();
+            data.program = program;
+            if data.percussion {
+                PERCUSSION_BANK
+            } else if data.bank_msb == 0 {
+                data.bank_lsb
+            } else {
+                data.bank_msb
+            }
+        };
+
+        self.channel_sf.borrow_mut().set_preset(bank, program);
+        self.update_spawners(key_events, offset);
+    }
+
+    /// Switches between instruments and drum kits, keeping the selected program
+    fn set_percussion(
+        &self,
+        percussion: bool,
+        key_events: &mut [SingleBorrowRef<Vec<(u32, NoteEvent)>>],
+        offset: u32,
+    ) {
+        let program = {
+            let mut data = self.control_event_data.borrow_mut();
+            data.percussion = percussion;
+            data.program
+        };
+        self.program_change(program, key_events, offset);
+
+        self.voice_control_data.borrow_mut().percussion = percussion;
+        self.push_control_snapshot(key_events, offset);
     }
 
-    pub fn set_soundfonts(&self, soundfonts: Vec<Arc<dyn SoundfontBase>>) {
-        self.params
-            .write()
-            .unwrap()
-            .channel_sf
-            .set_soundfonts(soundfonts)
+    /// Combines the pitch bend and the channel tuning into the voice pitch multiplier
+    fn update_pitch_multiplier(&self) -> bool {
+        let semitones = {
+            let data = self.control_event_data.borrow();
+            data.pitch_bend + data.coarse_tune + data.fine_tune / 100.0
+        };
+        let multiplier = 2.0f32.powf(semitones / 12.0);
+        self.voice_control_data.borrow_mut().voice_pitch_multiplier = multiplier;
+        true
     }
 
-    pub fn process_control_event(&self, event: ControlEvent) {
+    /// Sets the glide speed of voices, which only glide while the portamento pedal is pressed
+    fn update_portamento(&self) -> bool {
+        let rate = {
+            let data = self.control_event_data.borrow();
+            if data.portamento {
+                data.portamento_rate
+            } else {
+                f32::INFINITY
+            }
+        };
+        self.voice_control_data.borrow_mut().portamento_rate = rate;
+        true
+    }
+
+    /// Applies an MTS SysEx message to the channel's key tuning table
+    fn process_sysex(&self, message: &[u8]) -> bool {
+        let mut control = self.voice_control_data.borrow_mut();
+        let mut tuning = control.tuning.as_deref().cloned().unwrap_or_default();
+        if tuning.apply_mts_sysex(message) {
+            control.tuning = Some(Arc::new(tuning));
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Processes a control event, returning whether the voice control data changed
+    pub fn process_control_event(&self, event: ControlEvent) -> bool {
         match event {
             ControlEvent::Raw(controller, value) => match controller {
+                // The bank select only applies on the next program change
+                0x00 => {
+                    self.control_event_data.borrow_mut().bank_msb = value;
+                    false
+                }
+                0x20 => {
+                    self.control_event_data.borrow_mut().bank_lsb = value;
+                    false
+                }
+                0x01 => {
+                    let modulation = value as f32 / 127.0;
+                    self.process_control_event(ControlEvent::Modulation(modulation))
+                }
+                0x07 => {
+                    let volume = value as f32 / 127.0;
+                    self.process_control_event(ControlEvent::Volume(volume))
+                }
+                0x0A => {
+                    let pan = ((value as f32 - 64.0) / 63.0).max(-1.0);
+                    self.process_control_event(ControlEvent::Pan(pan))
+                }
+                0x0B => {
+                    let expression = value as f32 / 127.0;
+                    self.process_control_event(ControlEvent::Expression(expression))
+                }
+                // Up to a quarter of a second per semitone, with 0 being instant
+                0x05 => {
+                    let time = (value as f32 / 127.0).powi(2) * 0.25;
+                    self.process_control_event(ControlEvent::PortamentoTime(time))
+                }
+                0x41 => self.process_control_event(ControlEvent::Portamento(value >= 64)),
                 0x64 => {
                     self.control_event_data.borrow_mut().selected_lsb = value as i8;
+                    false
                 }
                 0x65 => {
                     self.control_event_data.borrow_mut().selected_msb = value as i8;
+                    false
                 }
                 0x06 | 0x26 => {
                     let (lsb, msb) = {
                         let data = self.control_event_data.borrow();
                         (data.selected_lsb, data.selected_msb)
                     };
-                    if lsb == 0 && msb == 0 {
-                        match controller {
-                            0x06 => {
-                                self.control_event_data
-                                    .borrow_mut()
-                                    .pitch_bend_sensitivity_msb = value
-                            }
-                            0x26 => {
-                                self.control_event_data
-                                    .borrow_mut()
-                                    .pitch_bend_sensitivity_lsb = value
+                    match (msb, lsb) {
+                        // Pitch bend sensitivity
+                        (0, 0) => {
+                            match controller {
+                                0x06 => {
+                                    self.control_event_data
+                                        .borrow_mut()
+                                        .pitch_bend_sensitivity_msb = value
+                                }
+                                0x26 => {
+                                    self.control_event_data
+                                        .borrow_mut()
+                                        .pitch_bend_sensitivity_lsb = value
+                                }
+                                _ => (),
                             }
-                            _ => (),
-                        }
 
-                        let sensitivity = {
-                            let data = self.control_event_data.borrow();
-                            (data.pitch_bend_sensitivity_msb as f32)
-                                + (data.pitch_bend_sensitivity_lsb as f32) / 100.0
-                        };
+                            let sensitivity = {
+                                let data = self.control_event_data.borrow();
+                                (data.pitch_bend_sensitivity_msb as f32)
+                                    + (data.pitch_bend_sensitivity_lsb as f32) / 100.0
+                            };
 
-                        self.process_control_event(ControlEvent::PitchBendSensitivity(sensitivity))
+                            self.process_control_event(ControlEvent::PitchBendSensitivity(
+                                sensitivity,
+                            ))
+                        }
+                        // Channel fine tuning, with 0x2000 as the center
+                        (0, 1) => {
+                            let fine_tune = {
+                                let mut data = self.control_event_data.borrow_mut();
+                                match controller {
+                                    0x06 => data.fine_tune_msb = value,
+                                    _ => data.fine_tune_lsb = value,
+                                }
+                                ((data.fine_tune_msb as i32) << 7 | data.fine_tune_lsb as i32)
+                                    - 0x2000
+                            };
+                            let cents = fine_tune as f32 / 0x2000 as f32 * 100.0;
+                            self.process_control_event(ControlEvent::FineTune(cents))
+                        }
+                        // Channel coarse tuning, which only uses the MSB
+                        (0, 2) if controller == 0x06 => {
+                            let semitones = value as f32 - 64.0;
+                            self.process_control_event(ControlEvent::CoarseTune(semitones))
+                        }
+                        _ => false,
                     }
                 }
-                _ => {}
+                _ => false,
             },
             ControlEvent::PitchBendSensitivity(sensitivity) => {
                 let pitch_bend = {
@@ -236,7 +587,7 @@ impl VoiceChannelData {
                     data.pitch_bend_sensitivity = sensitivity;
                     data.pitch_bend_sensitivity * data.pitch_bend_value
                 };
-                self.process_control_event(ControlEvent::PitchBend(pitch_bend));
+                self.process_control_event(ControlEvent::PitchBend(pitch_bend))
             }
             ControlEvent::PitchBendValue(value) => {
                 let pitch_bend = {
@@ -244,17 +595,105 @@ impl VoiceChannelData {
                     data.pitch_bend_value = value;
                     data.pitch_bend_sensitivity * data.pitch_bend_value
                 };
-                self.process_control_event(ControlEvent::PitchBend(pitch_bend));
+                self.process_control_event(ControlEvent::PitchBend(pitch_bend))
             }
             ControlEvent::PitchBend(value) => {
-                let multiplier = 2.0f32.powf(value / 12.0);
-                self.voice_control_data.borrow_mut().voice_pitch_multiplier = multiplier;
-                self.propagate_voice_controls();
+                self.control_event_data.borrow_mut().pitch_bend = value;
+                self.update_pitch_multiplier()
+            }
+            ControlEvent::FineTune(cents) => {
+                self.control_event_data.borrow_mut().fine_tune = cents;
+                self.update_pitch_multiplier()
+            }
+            ControlEvent::CoarseTune(semitones) => {
+                self.control_event_data.borrow_mut().coarse_tune = semitones;
+                self.update_pitch_multiplier()
+            }
+            // The volume curves are squared, roughly following the MIDI recommended 40log10 curve
+            ControlEvent::Volume(volume) => {
+                let volume = volume.clamp(0.0, 1.0);
+                self.voice_control_data.borrow_mut().volume = volume * volume;
+                true
+            }
+            ControlEvent::Expression(expression) => {
+                let expression = expression.clamp(0.0, 1.0);
+                self.voice_control_data.borrow_mut().expression = expression * expression;
+                true
+            }
+            ControlEvent::Pan(pan) => {
+                self.voice_control_data.borrow_mut().pan = pan.clamp(-1.0, 1.0);
+                true
+            }
+            ControlEvent::Modulation(modulation) => {
+                self.voice_control_data.borrow_mut().modulation = modulation.clamp(0.0, 1.0);
+                true
+            }
+            ControlEvent::ChannelPressure(pressure) => {
+                self.voice_control_data.borrow_mut().channel_pressure = pressure.clamp(0.0, 1.0);
+                true
+            }
+            ControlEvent::Portamento(portamento) => {
+                self.control_event_data.borrow_mut().portamento = portamento;
+                self.update_portamento()
+            }
+            ControlEvent::PortamentoTime(time) => {
+                let sample_rate = self
+                    .params
+                    .read()
+                    .unwrap()
+                    .constant
+                    .stream_params
+                    .sample_rate;
+                let rate = 1.0 / (time.max(0.0) * sample_rate as f32);
+                self.control_event_data.borrow_mut().portamento_rate = rate;
+                self.update_portamento()
             }
         }
     }
 }
 
+/// Takes the events that land within the next `frames` frames in order, moving the
+/// remaining events back by `frames` so that they land in a later render.
+fn take_block_events<T>(
+    events: &mut Vec<(u32, T)>,
+    frames: usize,
+) -> std::vec::Drain<'_, (u32, T)> {
+    events.sort_by_key(|(offset, _)| *offset);
+
+    let split = events
+        .iter()
+        .position(|(offset, _)| *offset as usize >= frames)
+        .unwrap_or(events.len());
+    for (offset, _) in events[split..].iter_mut() {
+        *offset -= frames as u32;
+    }
+
+    events.drain(..split)
+}
+
+/// Converts pedal controllers and note related channel mode messages into their key event
+fn key_control_event(control: &ControlEvent) -> Option<NoteEvent> {
+    match *control {
+        ControlEvent::Raw(0x40, value) => Some(NoteEvent::Damper(value >= 64)),
+        ControlEvent::Raw(0x42, value) => Some(NoteEvent::Sostenuto(value >= 64)),
+        ControlEvent::Raw(0x43, value) => Some(NoteEvent::SoftPedal(value >= 64)),
+        ControlEvent::Raw(0x78, _) => Some(NoteEvent::AllNotesKilled),
+        ControlEvent::Raw(0x7B, _) => Some(NoteEvent::AllNotesOff),
+        _ => None,
+    }
+}
+
+/// Sends an event to every key, ordered with the note events
+fn push_to_all_keys(
+    key_events: &mut [SingleBorrowRef<Vec<(u32, NoteEvent)>>],
+    offset: u32,
+    event: NoteEvent,
+) {
+    for events in key_events.iter_mut() {
+        events.push((offset, event.clone()));
+    }
+}
+
 impl VoiceChannel {
     pub fn new(
         sample_rate: u32,
@@ -275,27 +714,107 @@ impl VoiceChannel {
         self.push_events_iter(std::iter::once(event));
     }
 
+    /// Pushes events that apply at the start of the next render
     pub fn push_events_iter<T: Iterator<Item = ChannelEvent>>(&self, iter: T) {
+        self.push_timed_events_iter(iter.map(|e| (0, e)));
+    }
+
+    /// Pushes events along with their offset in frames from the start of the next render.
+    /// Events past the end of the render are kept for the following ones.
+    pub fn push_timed_events_iter<T: Iterator<Item = (u32, ChannelEvent)>>(&self, iter: T) {
         let data = self.data.lock().unwrap();
         let mut key_events = data
             .key_voices
             .iter()
             .map(|k| k.event_cache.borrow())
             .to_vec();
-        for e in iter {
+        for (offset, e) in iter {
             match e {
                 ChannelEvent::NoteOn { key, vel } => {
-                    let ev = NoteEvent::On(vel);
-                    key_events[key as usize].push(ev);
+                    data.note_on(&mut key_events, offset, key, vel)
+                }
+                ChannelEvent::NoteOff { key } => data.note_off(&mut key_events, offset, key),
+                ChannelEvent::PolyAftertouch { key, value } => {
+                    let key = data.note_router.borrow().voice_key(key);
+                    let pressure = value.clamp(0.0, 1.0);
+                    key_events[key as usize].push((offset, NoteEvent::Pressure(pressure)));
+                }
+                ChannelEvent::AllNotesOff => {
+                    data.note_router.borrow_mut().clear();
+                    push_to_all_keys(&mut key_events, offset, NoteEvent::AllNotesOff)
+                }
+                ChannelEvent::AllNotesKilled => {
+                    data.note_router.borrow_mut().clear();
+                    push_to_all_keys(&mut key_events, offset, NoteEvent::AllNotesKilled)
+                }
+                ChannelEvent::Control(ControlEvent::Raw(0x54, key)) => {
+                    data.note_router.borrow_mut().set_portamento_source(key);
+                }
+                ChannelEvent::Control(ControlEvent::Raw(controller @ 0x7E..=0x7F, _)) => {
+                    data.set_mono(controller == 0x7E, &mut key_events, offset);
+                }
+                ChannelEvent::Control(ControlEvent::Raw(0x79, _)) => {
+                    // Resetting the controllers also lifts the pedals and the keys' aftertouch
+                    data.reset_controllers();
+                    for ev in [
+                        NoteEvent::Damper(false),
+                        NoteEvent::Sostenuto(false),
+                        NoteEvent::SoftPedal(false),
+                        NoteEvent::Pressure(0.0),
+                    ]
+                    .iter()
+                    {
+                        push_to_all_keys(&mut key_events, offset, ev.clone());
+                    }
+                    data.push_control_snapshot(&mut key_events, offset);
+                }
+                ChannelEvent::Control(control) => match key_control_event(&control) {
+                    Some(ev) => {
+                        if let NoteEvent::AllNotesOff | NoteEvent::AllNotesKilled = ev {
+                            data.note_router.borrow_mut().clear();
+                        }
+                        push_to_all_keys(&mut key_events, offset, ev)
+                    }
+                    None => {
+                        if data.process_control_event(control) {
+                            data.push_control_snapshot(&mut key_events, offset);
+                        }
+                    }
+                },
+                ChannelEvent::ProgramChange(program) => {
+                    data.program_change(program, &mut key_events, offset)
+                }
+                ChannelEvent::SetPercussionMode(percussion) => {
+                    data.set_percussion(percussion, &mut key_events, offset)
+                }
+                ChannelEvent::SetSoundfonts(soundfonts) => {
+                    data.set_soundfonts(soundfonts, &mut key_events, offset)
+                }
+                ChannelEvent::SetPanLaw(pan_law) => {
+                    data.voice_control_data.borrow_mut().pan_law = pan_law;
+                    data.push_control_snapshot(&mut key_events, offset);
                 }
-                ChannelEvent::NoteOff { key } => {
-                    let ev = NoteEvent::Off;
-                    key_events[key as usize].push(ev);
+                ChannelEvent::SetLayerLimit(layers) => {
+                    data.spawn_params.borrow_mut().layers = layers;
+                    data.push_spawn_snapshot(&mut key_events, offset);
                 }
-                ChannelEvent::Control(control) => {
-                    data.process_control_event(control);
+                ChannelEvent::SetVoiceStealPolicy(policy) => {
+                    data.spawn_params.borrow_mut().steal_policy = policy;
+                    data.push_spawn_snapshot(&mut key_events, offset);
+                }
+                ChannelEvent::SetVelocityCurve(curve) => {
+                    data.voice_control_data.borrow_mut().velocity_curve = curve;
+                    data.push_control_snapshot(&mut key_events, offset);
+                }
+                ChannelEvent::SetTuning(tuning) => {
+                    data.voice_control_data.borrow_mut().tuning = tuning;
+                    data.push_control_snapshot(&mut key_events, offset);
+                }
+                ChannelEvent::SysEx(message) => {
+                    if data.process_sysex(&message) {
+                        data.push_control_snapshot(&mut key_events, offset);
+                    }
                 }
-                ChannelEvent::SetSoundfonts(soundfonts) => data.set_soundfonts(soundfonts),
             }
         }
     }
@@ -317,3 +836,186 @@ impl AudioPipe for VoiceChannel {
         data.push_key_events_and_render(out);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        soundfont::VoiceSpawner,
+        voice::{Voice, VoiceBase, VoiceGeneratorBase, VoiceSampleGenerator},
+    };
+
+    /// A voice that plays a constant level, and ends as soon as it is released
+    struct LevelVoice {
+        level: f32,
+        released: bool,
+    }
+
+    impl VoiceGeneratorBase for LevelVoice {
+        fn ended(&self) -> bool {
+            self.released
+        }
+
+        fn signal_release(&mut self) {
+            self.released = true;
+        }
+
+        fn process_controls(&mut self, _control: &VoiceControlData) {}
+    }
+
+    impl VoiceSampleGenerator for LevelVoice {
+        fn render_to(&mut self, buffer: &mut [f32]) {
+            for sample in buffer.iter_mut() {
+                *sample += self.level;
+            }
+        }
+    }
+
+    struct LevelSpawner(f32);
+
+    impl VoiceSpawner for LevelSpawner {
+        fn spawn_voice(&self, _control: &VoiceControlData) -> Box<dyn Voice> {
+            let voice = LevelVoice {
+                level: self.0,
+                released: false,
+            };
+            Box::new(VoiceBase::new(127, voice))
+        }
+    }
+
+    /// A soundfont whose voices play at a level of the program number plus one
+    #[derive(Debug)]
+    struct ProgramSoundfont(AudioStreamParams);
+
+    impl SoundfontBase for ProgramSoundfont {
+        fn stream_params(&self) -> &AudioStreamParams {
+            &self.0
+        }
+
+        fn get_attack_voice_spawners_at(
+            &self,
+            _bank: u8,
+            preset: u8,
+            _key: u8,
+            _vel: u8,
+        ) -> Vec<Box<dyn VoiceSpawner>> {
+            vec![Box::new(LevelSpawner(preset as f32 + 1.0))]
+        }
+
+        fn get_release_voice_spawners_at(
+            &self,
+            _bank: u8,
+            _preset: u8,
+            _key: u8,
+            _vel: u8,
+        ) -> Vec<Box<dyn VoiceSpawner>> {
+            vec![]
+        }
+    }
+
+    fn new_test_channel() -> VoiceChannel {
+        let channel = VoiceChannel::new(48000, 1, None);
+        let soundfont = ProgramSoundfont(AudioStreamParams::new(48000, 1));
+        channel.process_event(ChannelEvent::SetSoundfonts(vec![Arc::new(soundfont)]));
+        channel
+    }
+
+    /// Sends the events, renders them and returns the amount of playing voices
+    fn voices_after(channel: &mut VoiceChannel, events: Vec<ChannelEvent>) -> u64 {
+        channel.push_events_iter(events.into_iter());
+        channel.read_samples(&mut [0.0; 16]);
+        channel.get_channel_stats().voice_count()
+    }
+
+    fn sostenuto(value: u8) -> ChannelEvent {
+        ChannelEvent::Control(ControlEvent::Raw(0x42, value))
+    }
+
+    #[test]
+    fn test_program_change_within_render() {
+        let mut channel = new_test_channel();
+
+        // Notes before the program change keep the previous program, even on the same frame
+        channel.push_timed_events_iter(
+            vec![
+                (0, ChannelEvent::NoteOn { key: 60, vel: 127 }),
+                (16, ChannelEvent::NoteOn { key: 62, vel: 127 }),
+                (16, ChannelEvent::ProgramChange(1)),
+                (16, ChannelEvent::NoteOn { key: 64, vel: 127 }),
+            ]
+            .into_iter(),
+        );
+
+        let mut out = vec![0.0; 32];
+        channel.read_samples(&mut out);
+        assert!(out[..16].iter().all(|&sample| sample == 1.0));
+        assert!(out[16..].iter().all(|&sample| sample == 4.0));
+    }
+
+    #[test]
+    fn test_sostenuto_repeated_value() {
+        let mut channel = new_test_channel();
+
+        let events = vec![
+            ChannelEvent::NoteOn { key: 60, vel: 127 },
+            sostenuto(100),
+            ChannelEvent::NoteOff { key: 60 },
+        ];
+        assert_eq!(voices_after(&mut channel, events), 1);
+
+        // Another pressed value neither releases nor latches anything
+        assert_eq!(voices_after(&mut channel, vec![sostenuto(127)]), 1);
+        assert_eq!(voices_after(&mut channel, vec![sostenuto(0)]), 0);
+    }
+
+    #[test]
+    fn test_sostenuto_note_after_press() {
+        let mut channel = new_test_channel();
+
+        // Notes struck after the pedal is pressed aren't held, even on a latched key
+        let events = vec![
+            ChannelEvent::NoteOn { key: 60, vel: 127 },
+            sostenuto(127),
+            ChannelEvent::NoteOn { key: 60, vel: 127 },
+            ChannelEvent::NoteOn { key: 62, vel: 127 },
+        ];
+        assert_eq!(voices_after(&mut channel, events), 3);
+
+        let events = vec![
+            ChannelEvent::NoteOff { key: 62 },
+            ChannelEvent::NoteOff { key: 60 },
+            ChannelEvent::NoteOff { key: 60 },
+        ];
+        assert_eq!(voices_after(&mut channel, events), 1);
+        assert_eq!(voices_after(&mut channel, vec![sostenuto(0)]), 0);
+    }
+
+    #[test]
+    fn test_reset_controllers_keeps_rpn_values() {
+        let channel = new_test_channel();
+        let control = |control| ChannelEvent::Control(ControlEvent::Raw(control, 0));
+        let pitch_multiplier = || {
+            let data = channel.data.lock().unwrap();
+            let multiplier = data.voice_control_data.borrow().voice_pitch_multiplier;
+            multiplier
+        };
+
+        // A pitch bend range of 12 semitones, and a bend to the top of it
+        channel.push_events_iter(
+            vec![
+                control(0x65),
+                control(0x64),
+                ChannelEvent::Control(ControlEvent::Raw(0x06, 12)),
+                ChannelEvent::Control(ControlEvent::PitchBendValue(1.0)),
+            ]
+            .into_iter(),
+        );
+        assert!((pitch_multiplier() - 2.0).abs() < 0.001);
+
+        // The bend is reset, but the range set through the RPN is kept
+        channel.process_event(control(0x79));
+        assert!((pitch_multiplier() - 1.0).abs() < 0.001);
+        channel.process_event(ChannelEvent::Control(ControlEvent::PitchBendValue(1.0)));
+        assert!((pitch_multiplier() - 2.0).abs() < 0.001);
+    }
+}
@@ -1,76 +1,115 @@
-use std::{ops::Deref, sync::Arc};
+use std::{collections::VecDeque, sync::Arc};
 
-use crate::{
-    soundfont::SoundfontBase,
-    voice::{Voice, VoiceControlData},
-};
+use crate::soundfont::SoundfontBase;
 
 use super::voice_spawner::VoiceSpawnerMatrix;
 
-pub struct ChannelSoundfont {
-    soundfonts: Vec<Arc<dyn SoundfontBase>>,
-    matrix: VoiceSpawnerMatrix,
-}
+/// The bank of the drum kits, as used by SF2 soundfonts
+pub const PERCUSSION_BANK: u8 = 128;
 
-impl Deref for ChannelSoundfont {
-    type Target = VoiceSpawnerMatrix;
+/// How many of the recently used presets keep their spawner matrix around
+const CACHED_PRESETS: usize = 16;
 
-    #[inline(always)]
-    fn deref(&self) -> &Self::Target {
-        &self.matrix
-    }
+pub struct ChannelSoundfont {
+    soundfonts: Vec<Arc<dyn SoundfontBase>>,
+    bank: u8,
+    preset: u8,
+    matrix: Arc<VoiceSpawnerMatrix>,
+    /// The matrices of the recently used presets, from the least to the most
+    /// recently used, so that switching back to them doesn't rebuild them
+    cache: VecDeque<((u8, u8), Arc<VoiceSpawnerMatrix>)>,
 }
 
 impl ChannelSoundfont {
     pub fn new() -> Self {
         ChannelSoundfont {
             soundfonts: Vec::new(),
-            matrix: VoiceSpawnerMatrix::new(),
+            bank: 0,
+            preset: 0,
+            matrix: Arc::new(VoiceSpawnerMatrix::new()),
+            cache: VecDeque::new(),
         }
     }
 
     pub fn set_soundfonts(&mut self, soundfonts: Vec<Arc<dyn SoundfontBase>>) {
         self.soundfonts = soundfonts;
-        self.rebuild_matrix();
+        self.cache.clear();
+        self.select_matrix();
+    }
+
+    /// The spawner matrix of the selected preset
+    pub fn matrix(&self) -> Arc<VoiceSpawnerMatrix> {
+        self.matrix.clone()
+    }
+
+    /// Selects the bank and program that new voices are spawned from.
+    /// Voices that are already playing keep their instrument.
+    pub fn set_preset(&mut self, bank: u8, preset: u8) {
+        if (bank, preset) != (self.bank, self.preset) {
+            self.bank = bank;
+            self.preset = preset;
+            self.select_matrix();
+        }
     }
 
-    fn rebuild_matrix(&mut self) {
+    /// Switches to the matrix of the selected preset, only building it if it
+    /// isn't one of the recently used ones
+    fn select_matrix(&mut self) {
+        // Like GS capital tones, a missing bank falls back to the same program in bank 0.
+        // A missing drum kit falls back to the standard kit instead.
+        let has_preset =
+            |bank, preset| self.soundfonts.iter().any(|sf| sf.has_preset(bank, preset));
+        let selected = if has_preset(self.bank, self.preset) {
+            (self.bank, self.preset)
+        } else if self.bank == PERCUSSION_BANK {
+            (PERCUSSION_BANK, 0)
+        } else {
+            (0, self.preset)
+        };
+
+        let matrix = match self
+            .cache
+            .iter()
+            .position(|(preset, _)| *preset == selected)
+        {
+            Some(index) => self.cache.remove(index).unwrap().1,
+            None => Arc::new(self.build_matrix(selected.0, selected.1)),
+        };
+
+        if self.cache.len() >= CACHED_PRESETS {
+            self.cache.pop_front();
+        }
+        self.cache.push_back((selected, matrix.clone()));
+        self.matrix = matrix;
+    }
+
+    fn build_matrix(&self, bank: u8, preset: u8) -> VoiceSpawnerMatrix {
+        let mut matrix = VoiceSpawnerMatrix::new();
+
+        let soundfonts: Vec<&Arc<dyn SoundfontBase>> = self
+            .soundfonts
+            .iter()
+            .filter(|sf| sf.has_preset(bank, preset))
+            .collect();
+
         for k in 0..128u8 {
             for v in 0..128u8 {
-                let vec = self
-                    .soundfonts
+                let vec = soundfonts
                     .iter()
-                    .map(|sf| sf.get_attack_voice_spawners_at(k, v))
-                    .find(|vec| vec.len() > 0)
-                    .unwrap_or_else(|| vec![]);
-                self.matrix.set_spawners_attack(k, v, vec);
+                    .map(|sf| sf.get_attack_voice_spawners_at(bank, preset, k, v))
+                    .find(|vec| !vec.is_empty())
+                    .unwrap_or_default();
+                matrix.set_spawners_attack(k, v, vec);
 
-                let vec = self
-                    .soundfonts
+                let vec = soundfonts
                     .iter()
-                    .map(|sf| sf.get_release_voice_spawners_at(k, v))
-                    .find(|vec| vec.len() > 0)
-                    .unwrap_or_else(|| vec![]);
-                self.matrix.set_spawners_release(k, v, vec);
+                    .map(|sf| sf.get_release_voice_spawners_at(bank, preset, k, v))
+                    .find(|vec| !vec.is_empty())
+                    .unwrap_or_default();
+                matrix.set_spawners_release(k, v, vec);
             }
         }
-    }
-
-    pub fn spawn_voices_attack<'a>(
-        &'a self,
-        control: &'a VoiceControlData,
-        key: u8,
-        vel: u8,
-    ) -> impl Iterator<Item = Box<dyn Voice>> + 'a {
-        self.matrix.spawn_voices_attack(control, key, vel)
-    }
 
-    pub fn spawn_voices_release<'a>(
-        &'a self,
-        control: &'a VoiceControlData,
-        key: u8,
-        vel: u8,
-    ) -> impl Iterator<Item = Box<dyn Voice>> + 'a {
-        self.matrix.spawn_voices_release(control, key, vel)
+        matrix
     }
 }
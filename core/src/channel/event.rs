@@ -1,20 +1,114 @@
 use std::sync::Arc;
 
-use crate::soundfont::SoundfontBase;
+use crate::{
+    soundfont::SoundfontBase,
+    tuning::Tuning,
+    voice::{VelocityCurve, VoiceControlData},
+};
 
-#[derive(Debug)]
+use super::{params::VoiceSpawnParams, PanLaw, VoiceStealPolicy};
+
+#[derive(Debug, Clone)]
 pub enum NoteEvent {
     On(u8),
     Off,
+
+    /// The damper (sustain) pedal being pressed or lifted
+    Damper(bool),
+
+    /// The sostenuto pedal being pressed or lifted
+    Sostenuto(bool),
+
+    /// The soft pedal being pressed or lifted
+    SoftPedal(bool),
+
+    /// The channel's voice control data changing
+    Control(Arc<VoiceControlData>),
+
+    /// The channel's preset, layer limit or voice steal policy changing
+    SpawnParams(Arc<VoiceSpawnParams>),
+
+    /// The key's polyphonic aftertouch changing, between 0 and 1
+    Pressure(f32),
+
+    /// The next note on glides from the pitch of another key, at a rate in
+    /// semitones per sample
+    Glide {
+        from: u8,
+        rate: f32,
+    },
+
+    /// Moves the key's voices to the pitch of another key without retriggering
+    /// them, for legato notes in mono mode
+    Legato(u8),
+
+    /// Releases every note, except for the ones held by a pedal
+    AllNotesOff,
+
+    /// Stops every voice immediately
+    AllNotesKilled,
+
+    /// A note of a choke group being played, which quickly fades out the
+    /// voices stopped by that group
+    Choke(u32),
 }
 
 #[derive(Debug, Clone)]
 pub enum ChannelEvent {
-    NoteOn { key: u8, vel: u8 },
-    NoteOff { key: u8 },
+    NoteOn {
+        key: u8,
+        vel: u8,
+    },
+    NoteOff {
+        key: u8,
+    },
+
+    /// The pressure on a held key, between 0 and 1
+    PolyAftertouch {
+        key: u8,
+        value: f32,
+    },
+
     Control(ControlEvent),
 
+    /// Releases every note of the channel, like a note off for each key
+    AllNotesOff,
+
+    /// Stops every voice of the channel immediately, without a release
+    AllNotesKilled,
+
+    /// Switches new voices to a program, in the bank selected through the
+    /// bank select controllers (CC0 and CC32)
+    ProgramChange(u8),
+
+    /// Switches the channel between instruments and drum kits. Drum kits are
+    /// selected from the percussion bank by the program, and their unlooped
+    /// samples play to the end regardless of note offs. The channel 10 of a
+    /// synth starts in percussion mode, following General MIDI.
+    SetPercussionMode(bool),
+
     SetSoundfonts(Vec<Arc<dyn SoundfontBase>>),
+    SetPanLaw(PanLaw),
+
+    /// Sets the maximum amount of voices each key can play at once, or removes
+    /// the limit if `None`. New notes past the limit steal voices from the key.
+    SetLayerLimit(Option<usize>),
+
+    /// Sets which voices are stolen when a key goes over the layer limit
+    SetVoiceStealPolicy(Arc<dyn VoiceStealPolicy>),
+
+    /// Overrides the velocity curve of the soundfonts for new voices, or
+    /// restores the soundfonts' curves if `None`
+    SetVelocityCurve(Option<VelocityCurve>),
+
+    /// Retunes the keys of new and playing voices, or restores equal
+    /// temperament if `None`
+    SetTuning(Option<Arc<Tuning>>),
+
+    /// A system exclusive message, with or without its 0xF0 and 0xF7 bytes.
+    /// MIDI Tuning Standard single note changes and bulk dumps retune the
+    /// channel's keys, and other messages are ignored.
+    SysEx(Vec<u8>),
 }
 
 #[derive(Debug, Clone)]
@@ -29,4 +123,31 @@ pub enum ControlEvent {
 
     /// The pitch bend, product of value * sensitivity
     PitchBend(f32),
+
+    /// The channel fine tuning, in cents
+    FineTune(f32),
+
+    /// The channel coarse tuning, in semitones
+    CoarseTune(f32),
+
+    /// The channel volume, between 0 and 1
+    Volume(f32),
+
+    /// The channel expression, between 0 and 1
+    Expression(f32),
+
+    /// The channel pan, between -1 (left) and 1 (right)
+    Pan(f32),
+
+    /// The modulation wheel, between 0 and 1
+    Modulation(f32),
+
+    /// The channel pressure (aftertouch), between 0 and 1
+    ChannelPressure(f32),
+
+    /// The portamento pedal being pressed or lifted
+    Portamento(bool),
+
+    /// The portamento glide time, in seconds per semitone
+    PortamentoTime(f32),
 }
@@ -1,58 +1,302 @@
-use std::sync::{
-    atomic::{AtomicU64, Ordering},
-    Arc,
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
 };
 
+use crate::AudioStreamParams;
+
 use super::{
-    channel_sf::ChannelSoundfont, event::NoteEvent, voice_buffer::VoiceBuffer, VoiceControlData,
+    event::NoteEvent, params::VoiceSpawnParams, voice_buffer::VoiceBuffer, VoiceControlData,
 };
 
+/// How long voices stopped by a choke group or stolen take to fade out, in seconds
+const KILL_FADE_TIME: f32 = 0.005;
+
+/// A note that hasn't been released yet
+struct HeldNote {
+    vel: u8,
+    /// The key time when the note was pressed, in seconds
+    start_time: f64,
+    /// The id of the voice group spawned by the note
+    group: usize,
+    /// Whether the note was held down when the sostenuto pedal was pressed,
+    /// which keeps it playing until the pedal is lifted
+    sostenuto: bool,
+}
+
 pub struct KeyData {
     key: u8,
     voices: VoiceBuffer,
+
+    /// Notes that are physically held down
+    held_notes: VecDeque<HeldNote>,
+    /// Notes that had a note off, but are kept playing by the damper or sostenuto pedal
+    sustained_notes: VecDeque<HeldNote>,
+
+    damper: bool,
+    sostenuto: bool,
+    soft_pedal: bool,
+
     last_voice_count: usize,
     shared_voice_counter: Arc<AtomicU64>,
+
+    stream_params: AudioStreamParams,
+    /// The amount of audio rendered for this key so far, in seconds
+    time: f64,
+
+    /// The channel's control data at the current position of the key
+    channel_control: Arc<VoiceControlData>,
+    /// The control data of the key's voices, derived from the channel's
+    control: Arc<VoiceControlData>,
+    /// The parameters that the key spawns and steals voices with
+    params: Arc<VoiceSpawnParams>,
+
+    /// The key played by the key's voices, which legato notes change in mono mode
+    playing_key: u8,
+    /// The key and rate (semitones per sample) that the next note glides from
+    glide: Option<(u8, f32)>,
+    /// The polyphonic aftertouch of the key, between 0 and 1
+    pressure: f32,
 }
 
 impl KeyData {
-    pub fn new(key: u8, shared_voice_counter: Arc<AtomicU64>) -> KeyData {
+    pub fn new(
+        key: u8,
+        shared_voice_counter: Arc<AtomicU64>,
+        stream_params: AudioStreamParams,
+        params: Arc<VoiceSpawnParams>,
+    ) -> KeyData {
         KeyData {
             key,
             voices: VoiceBuffer::new(),
+            held_notes: VecDeque::new(),
+            sustained_notes: VecDeque::new(),
+            damper: false,
+            sostenuto: false,
+            soft_pedal: false,
             last_voice_count: 0,
             shared_voice_counter,
+            stream_params,
+            time: 0.0,
+            channel_control: Arc::new(VoiceControlData::new_defaults()),
+            control: Arc::new(VoiceControlData::new_defaults()),
+            params,
+            playing_key: key,
+            glide: None,
+            pressure: 0.0,
         }
     }
 
-    pub fn send_event(
-        &mut self,
-        event: NoteEvent,
-        control: &VoiceControlData,
-        channel_sf: &ChannelSoundfont,
-        max_layers: Option<usize>,
-    ) {
+    pub fn send_event(&mut self, event: NoteEvent) {
         match event {
             NoteEvent::On(vel) => {
-                let voices = channel_sf.spawn_voices_attack(control, self.key, vel);
-                self.voices.push_voices(vel, voices, max_layers);
+                // The soft pedal plays notes as if they were struck more gently,
+                // which also picks softer velocity layers
+                let vel = if self.soft_pedal {
+                    ((vel as u32 * 3 / 4) as u8).max(1)
+                } else {
+                    vel
+                };
+
+                // A new note plays its own key, even if legato notes moved the previous
+                // one, and starts without the aftertouch of the previous note
+                if self.playing_key != self.key || self.pressure != 0.0 {
+                    self.playing_key = self.key;
+                    self.pressure = 0.0;
+                    self.update_control();
+                }
+
+                let glide_control = self.glide.take().map(|(from, rate)| {
+                    let mut control = (*self.control).clone();
+                    control.glide_from = self.semitones_to(from);
+                    control.portamento_rate = rate;
+                    control
+                });
+                let control = match &glide_control {
+                    Some(control) => control,
+                    None => &self.control,
+                };
+
+                // Voices over the layer limit are faded out like choked voices
+                let fade_samples = self.kill_fade_samples();
+                let params = &self.params;
+                let voices = params.spawners.spawn_voices_attack(control, self.key, vel);
+                let group = self.voices.push_voices(
+                    voices,
+                    params.layers,
+                    &*params.steal_policy,
+                    fade_samples,
+                );
+
+                self.held_notes.push_back(HeldNote {
+                    vel,
+                    start_time: self.time,
+                    group,
+                    sostenuto: false,
+                });
             }
             NoteEvent::Off => {
-                let vel = self.voices.release_next_voice();
-                if let Some(vel) = vel {
-                    let voices = channel_sf.spawn_voices_release(control, self.key, vel);
-                    self.voices.push_voices(vel, voices, max_layers);
+                if let Some(note) = self.held_notes.pop_front() {
+                    self.note_off(note);
+                }
+            }
+            NoteEvent::Damper(pressed) => {
+                self.damper = pressed;
+                self.release_sustained_notes();
+            }
+            NoteEvent::Sostenuto(pressed) => {
+                // Sostenuto only holds the notes that are down when the pedal is pressed,
+                // so repeated pedal values don't latch or unlatch anything
+                if pressed && !self.sostenuto {
+                    for note in self.held_notes.iter_mut() {
+                        note.sostenuto = true;
+                    }
+                } else if !pressed && self.sostenuto {
+                    for note in self.held_notes.iter_mut() {
+                        note.sostenuto = false;
+                    }
+                    for note in self.sustained_notes.iter_mut() {
+                        note.sostenuto = false;
+                    }
+                    self.release_sustained_notes();
+                }
+                self.sostenuto = pressed;
+            }
+            NoteEvent::SoftPedal(pressed) => {
+                self.soft_pedal = pressed;
+            }
+            NoteEvent::Control(control) => {
+                self.channel_control = control;
+                self.update_control();
+            }
+            NoteEvent::SpawnParams(params) => {
+                self.params = params;
+            }
+            NoteEvent::Pressure(pressure) => {
+                self.pressure = pressure;
+                self.update_control();
+            }
+            NoteEvent::Glide { from, rate } => {
+                self.glide = Some((from, rate));
+            }
+            NoteEvent::Legato(key) => {
+                self.playing_key = key;
+                self.update_control();
+            }
+            NoteEvent::AllNotesOff => {
+                while let Some(note) = self.held_notes.pop_front() {
+                    self.note_off(note);
+                }
+            }
+            NoteEvent::AllNotesKilled => {
+                self.held_notes.clear();
+                self.sustained_notes.clear();
+                self.voices.clear();
+                self.update_voice_count();
+                self.glide = None;
+                self.playing_key = self.key;
+                self.update_control();
+            }
+            NoteEvent::Choke(group) => {
+                let fade_samples = self.kill_fade_samples();
+                for voice in &mut self.voices.iter_voices_mut() {
+                    if voice.off_by() == Some(group) {
+                        voice.signal_kill(fade_samples);
+                    }
                 }
             }
         }
     }
 
-    pub fn process_controls(&mut self, control: &VoiceControlData) {
+    /// The length of the fade out of killed voices, in samples of the interleaved output
+    fn kill_fade_samples(&self) -> usize {
+        let frames = (self.stream_params.sample_rate as f32 * KILL_FADE_TIME) as usize;
+        frames * self.stream_params.channels as usize
+    }
+
+    /// Releases a note that is no longer held down, unless a pedal keeps it playing
+    fn note_off(&mut self, note: HeldNote) {
+        if self.damper || note.sostenuto {
+            self.sustained_notes.push_back(note);
+        } else {
+            self.release_note(note);
+        }
+    }
+
+    fn release_note(&mut self, note: HeldNote) {
+        self.voices.release_voice_group(note.group);
+
+        let held_time = (self.time - note.start_time) as f32;
+        let params = &self.params;
+        let voices =
+            params
+                .spawners
+                .spawn_voices_release(&self.control, self.key, note.vel, held_time);
+
+        // Release voices don't get a note off of their own, so they are spawned already released
+        let voices = voices.map(|mut voice| {
+            voice.signal_release();
+            voice
+        });
+        let fade_samples = self.kill_fade_samples();
+        self.voices
+            .push_voices(voices, params.layers, &*params.steal_policy, fade_samples);
+    }
+
+    /// Releases the notes kept by the pedals, if no pedal is holding them anymore
+    fn release_sustained_notes(&mut self) {
+        if self.damper {
+            return;
+        }
+
+        let (latched, released): (VecDeque<_>, VecDeque<_>) = self
+            .sustained_notes
+            .drain(..)
+            .partition(|note| note.sostenuto);
+        self.sustained_notes = latched;
+        for note in released {
+            self.release_note(note);
+        }
+    }
+
+    /// The pitch of another key relative to this one, in semitones
+    fn semitones_to(&self, key: u8) -> f32 {
+        match &self.channel_control.tuning {
+            Some(tuning) => 12.0 * (tuning.frequency(key) / tuning.frequency(self.key)).log2(),
+            None => key as f32 - self.key as f32,
+        }
+    }
+
+    /// Derives the control data of the key's voices from the channel's, applying
+    /// the pitch of legato notes and the key's aftertouch
+    fn update_control(&mut self) {
+        let channel_control = &self.channel_control;
+        let control = if self.playing_key == self.key && self.pressure == 0.0 {
+            channel_control.clone()
+        } else {
+            let mut control = (**channel_control).clone();
+            control.pitch_offset = self.semitones_to(self.playing_key);
+            control.key_pressure = self.pressure;
+            Arc::new(control)
+        };
+
+        self.process_controls(&control);
+        self.control = control;
+    }
+
+    fn process_controls(&mut self, control: &VoiceControlData) {
         for voice in &mut self.voices.iter_voices_mut() {
             voice.process_controls(control);
         }
     }
 
     pub fn render_to(&mut self, out: &mut [f32]) {
+        let frames = out.len() / self.stream_params.channels as usize;
+        self.time += frames as f64 / self.stream_params.sample_rate as f64;
+
         if !self.has_voices() {
             return;
         }
@@ -60,7 +304,11 @@ impl KeyData {
             voice.render_to(out);
         }
         self.voices.remove_ended_voices();
+        self.update_voice_count();
+    }
 
+    /// Applies the change in the key's voice count to the channel's voice counter
+    fn update_voice_count(&mut self) {
         let voice_count = self.voices.voice_count();
         let change = voice_count as i64 - self.last_voice_count as i64;
         if change < 0 {
@@ -0,0 +1,91 @@
+use crate::voice::VoiceControlData;
+
+/// How the channel pan is converted into left and right gains
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PanLaw {
+    /// Attenuates only the opposite side, keeping both sides at full volume when centered
+    #[default]
+    Balance,
+
+    /// Keeps the total power constant, both sides are at -3dB when centered
+    ConstantPower,
+
+    /// Keeps the sum of both sides constant, both sides are at -6dB when centered
+    Linear,
+}
+
+impl PanLaw {
+    /// Returns the left and right gains for a pan between -1 (left) and 1 (right)
+    pub fn gains(&self, pan: f32) -> (f32, f32) {
+        let pan = pan.clamp(-1.0, 1.0);
+        match self {
+            PanLaw::Balance => ((1.0 - pan).min(1.0), (1.0 + pan).min(1.0)),
+            PanLaw::ConstantPower => {
+                let angle = (pan + 1.0) * std::f32::consts::FRAC_PI_4;
+                (angle.cos(), angle.sin())
+            }
+            PanLaw::Linear => ((1.0 - pan) / 2.0, (1.0 + pan) / 2.0),
+        }
+    }
+}
+
+/// Applies the channel volume, expression and pan to the rendered channel audio,
+/// smoothing changes to avoid zipper noise.
+pub struct ChannelMixer {
+    channels: usize,
+    smoothing: f32,
+    left: f32,
+    right: f32,
+}
+
+impl ChannelMixer {
+    pub fn new(sample_rate: u32, channels: u16) -> ChannelMixer {
+        // Roughly 5ms to approach the target gain
+        let smoothing = 1.0 - (-1.0 / (0.005 * sample_rate as f32)).exp();
+
+        ChannelMixer {
+            channels: channels as usize,
+            smoothing,
+            left: 1.0,
+            right: 1.0,
+        }
+    }
+
+    pub fn apply(&mut self, out: &mut [f32], control: &VoiceControlData) {
+        let gain = control.volume * control.expression;
+        let (pan_left, pan_right) = if self.channels >= 2 {
+            control.pan_law.gains(control.pan)
+        } else {
+            (1.0, 1.0)
+        };
+        let (target_left, target_right) = (gain * pan_left, gain * pan_right);
+
+        if self.left == target_left && self.right == target_right {
+            if target_left != 1.0 || target_right != 1.0 {
+                for frame in out.chunks_mut(self.channels) {
+                    apply_frame(frame, target_left, target_right);
+                }
+            }
+            return;
+        }
+
+        for frame in out.chunks_mut(self.channels) {
+            self.left += (target_left - self.left) * self.smoothing;
+            self.right += (target_right - self.right) * self.smoothing;
+            apply_frame(frame, self.left, self.right);
+        }
+
+        // Snap to the target once it's close enough, so that the steady state is cheap
+        if (self.left - target_left).abs() < 0.0001 && (self.right - target_right).abs() < 0.0001 {
+            self.left = target_left;
+            self.right = target_right;
+        }
+    }
+}
+
+#[inline(always)]
+fn apply_frame(frame: &mut [f32], left: f32, right: f32) {
+    for (i, sample) in frame.iter_mut().enumerate() {
+        *sample *= if i == 1 { right } else { left };
+    }
+}
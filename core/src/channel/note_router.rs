@@ -0,0 +1,187 @@
+use super::event::NoteEvent;
+
+/// Turns the channel's note events into key events, keeping track of the notes
+/// needed for mono mode and portamento.
+///
+/// In mono mode, only the last held note sounds. A note played while another
+/// is held is legato: the sounding voices move to its pitch instead of being
+/// retriggered, and move back to the previous held note when it is released.
+pub struct NoteRouter {
+    mono: bool,
+    /// The keys held down in mono mode, in the order they were pressed
+    held_keys: Vec<u8>,
+    /// The key that owns the voices of the current mono note
+    sounding_key: Option<u8>,
+    /// The key played by the voices of the current mono note
+    playing_key: Option<u8>,
+    /// The last key played, which portamento glides start from
+    last_key: Option<u8>,
+    /// The key that the next note glides from, set by the portamento control (CC84)
+    portamento_source: Option<u8>,
+}
+
+impl NoteRouter {
+    pub fn new() -> Self {
+        NoteRouter {
+            mono: false,
+            held_keys: Vec::new(),
+            sounding_key: None,
+            playing_key: None,
+            last_key: None,
+            portamento_source: None,
+        }
+    }
+
+    /// Switches between mono and poly mode, forgetting the held notes
+    pub fn set_mono(&mut self, mono: bool) {
+        self.mono = mono;
+        self.clear();
+    }
+
+    pub fn set_portamento_source(&mut self, key: u8) {
+        self.portamento_source = Some(key.min(127));
+    }
+
+    /// Forgets the held notes, after they were all released or killed
+    pub fn clear(&mut self) {
+        self.held_keys.clear();
+        self.sounding_key = None;
+        self.playing_key = None;
+    }
+
+    /// The key that owns the voices playing a key, which differs from it
+    /// for legato notes in mono mode
+    pub fn voice_key(&self, key: u8) -> u8 {
+        match (self.playing_key, self.sounding_key) {
+            (Some(playing), Some(sounding)) if self.mono && playing == key => sounding,
+            _ => key,
+        }
+    }
+
+    /// Routes a note on. With portamento on, the note glides from the last key
+    /// played at `portamento_rate`, in semitones per sample.
+    pub fn note_on(
+        &mut self,
+        key: u8,
+        vel: u8,
+        portamento: bool,
+        portamento_rate: f32,
+        mut push: impl FnMut(u8, NoteEvent),
+    ) {
+        let glide_from = match self.portamento_source.take() {
+            Some(source) => Some(source),
+            None if portamento => self.last_key,
+            None => None,
+        };
+        self.last_key = Some(key);
+
+        if self.mono {
+            let legato = !self.held_keys.is_empty();
+            self.held_keys.retain(|held| *held != key);
+            self.held_keys.push(key);
+            self.playing_key = Some(key);
+
+            if let (true, Some(sounding)) = (legato, self.sounding_key) {
+                push(sounding, NoteEvent::Legato(key));
+                return;
+            }
+            self.sounding_key = Some(key);
+        }
+
+        if let Some(from) = glide_from.filter(|from| *from != key) {
+            let rate = portamento_rate;
+            push(key, NoteEvent::Glide { from, rate });
+        }
+        push(key, NoteEvent::On(vel));
+    }
+
+    /// Routes a note off. In mono mode, releasing the playing key moves back
+    /// to the previous held key, if there is one.
+    pub fn note_off(&mut self, key: u8, mut push: impl FnMut(u8, NoteEvent)) {
+        if !self.mono {
+            push(key, NoteEvent::Off);
+            return;
+        }
+
+        if !self.held_keys.contains(&key) {
+            return;
+        }
+        self.held_keys.retain(|held| *held != key);
+
+        if self.playing_key != Some(key) {
+            return;
+        }
+        let sounding = match self.sounding_key {
+            Some(sounding) => sounding,
+            None => return,
+        };
+
+        match self.held_keys.last() {
+            Some(&previous) => {
+                push(sounding, NoteEvent::Legato(previous));
+                self.playing_key = Some(previous);
+                self.last_key = Some(previous);
+            }
+            None => {
+                push(sounding, NoteEvent::Off);
+                self.sounding_key = None;
+                self.playing_key = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(router: &mut NoteRouter, on: Option<u8>, key: u8) -> Vec<(u8, String)> {
+        let mut events = Vec::new();
+        let push = |key, event| events.push((key, format!("{:?}", event)));
+        match on {
+            Some(vel) => router.note_on(key, vel, true, 0.5, push),
+            None => router.note_off(key, push),
+        }
+        events
+    }
+
+    fn expect(events: &[(u8, &str)]) -> Vec<(u8, String)> {
+        events.iter().map(|(k, e)| (*k, e.to_string())).collect()
+    }
+
+    #[test]
+    fn test_mono_legato() {
+        let mut router = NoteRouter::new();
+        router.set_mono(true);
+
+        // The first note spawns voices, and the following held notes move them
+        assert_eq!(
+            route(&mut router, Some(100), 60),
+            expect(&[(60, "On(100)")])
+        );
+        assert_eq!(
+            route(&mut router, Some(100), 64),
+            expect(&[(60, "Legato(64)")])
+        );
+        assert_eq!(
+            route(&mut router, Some(100), 67),
+            expect(&[(60, "Legato(67)")])
+        );
+
+        // Events for the playing key, such as aftertouch, go to the voices' key
+        assert_eq!(router.voice_key(67), 60);
+        assert_eq!(router.voice_key(64), 64);
+
+        // Last note priority: releasing a key that isn't playing changes nothing,
+        // and releasing the playing key moves back to the last held one
+        assert_eq!(route(&mut router, None, 64), expect(&[]));
+        assert_eq!(route(&mut router, None, 67), expect(&[(60, "Legato(60)")]));
+        assert_eq!(route(&mut router, None, 60), expect(&[(60, "Off")]));
+
+        // A new note glides from the last one with portamento
+        assert_eq!(
+            route(&mut router, Some(90), 72),
+            expect(&[(72, "Glide { from: 60, rate: 0.5 }"), (72, "On(90)")])
+        );
+    }
+}
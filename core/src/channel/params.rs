@@ -2,7 +2,10 @@ use std::sync::{atomic::AtomicU64, Arc};
 
 use crate::AudioStreamParams;
 
-use super::channel_sf::ChannelSoundfont;
+use super::{
+    steal_policy::{StealQuietest, VoiceStealPolicy},
+    voice_spawner::VoiceSpawnerMatrix,
+};
 
 #[derive(Debug, Clone)]
 pub struct VoiceChannelStats {
@@ -20,11 +23,18 @@ pub struct VoiceChannelConst {
 
 pub struct VoiceChannelParams {
     pub stats: VoiceChannelStats,
-    pub layers: Option<usize>,
-    pub channel_sf: ChannelSoundfont,
     pub constant: VoiceChannelConst,
 }
 
+/// The parameters that keys spawn and steal voices with. Keys get a snapshot of
+/// them along with their events, so that changes land on their exact frame.
+#[derive(Clone)]
+pub struct VoiceSpawnParams {
+    pub spawners: Arc<VoiceSpawnerMatrix>,
+    pub layers: Option<usize>,
+    pub steal_policy: Arc<dyn VoiceStealPolicy>,
+}
+
 impl VoiceChannelStats {
     pub fn new() -> Self {
         let voice_counter = Arc::new(AtomicU64::new(0));
@@ -34,12 +44,8 @@ impl VoiceChannelStats {
 
 impl VoiceChannelParams {
     pub fn new(sample_rate: u32, channels: u16) -> Self {
-        let channel_sf = ChannelSoundfont::new();
-
         Self {
             stats: VoiceChannelStats::new(),
-            layers: Some(4),
-            channel_sf,
             constant: VoiceChannelConst {
                 stream_params: AudioStreamParams::new(sample_rate, channels),
             },
@@ -47,6 +53,25 @@ impl VoiceChannelParams {
     }
 }
 
+impl VoiceSpawnParams {
+    pub fn new(spawners: Arc<VoiceSpawnerMatrix>) -> Self {
+        Self {
+            spawners,
+            layers: Some(4),
+            steal_policy: Arc::new(StealQuietest),
+        }
+    }
+}
+
+impl std::fmt::Debug for VoiceSpawnParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VoiceSpawnParams")
+            .field("layers", &self.layers)
+            .field("steal_policy", &self.steal_policy)
+            .finish()
+    }
+}
+
 impl VoiceChannelStatsReader {
     pub fn new(stats: VoiceChannelStats) -> Self {
         Self { stats }
@@ -0,0 +1,116 @@
+/// A group of voices spawned by the same note, which can be stolen to make
+/// room for new voices when a key plays too many of them.
+#[derive(Debug, Clone, Copy)]
+pub struct VoiceGroupInfo {
+    /// The id of the group, which increases with each note, so lower ids are older
+    pub id: usize,
+    /// The velocity of the note that spawned the group
+    pub velocity: u8,
+    /// Whether all the voices of the group are releasing
+    pub releasing: bool,
+    /// The highest amplitude envelope level of the group's voices, between 0 and 1
+    pub level: f32,
+}
+
+/// Picks the voices to fade out when a key plays more voices than the
+/// channel's layer limit allows.
+pub trait VoiceStealPolicy: Sync + Send + std::fmt::Debug {
+    /// Picks the group to steal, as an index into `groups`, which are ordered
+    /// from the oldest to the newest. Returning `None` keeps all the voices.
+    fn choose(&self, groups: &[VoiceGroupInfo]) -> Option<usize>;
+}
+
+/// The index of the group with the lowest value, picking the oldest one on ties
+fn lowest_by<T: PartialOrd>(
+    groups: &[VoiceGroupInfo],
+    value: impl Fn(&VoiceGroupInfo) -> T,
+) -> Option<usize> {
+    groups
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| {
+            value(a)
+                .partial_cmp(&value(b))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })
+        .map(|(i, _)| i)
+}
+
+/// Steals the oldest voices
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StealOldest;
+
+impl VoiceStealPolicy for StealOldest {
+    fn choose(&self, groups: &[VoiceGroupInfo]) -> Option<usize> {
+        if groups.is_empty() {
+            None
+        } else {
+            Some(0)
+        }
+    }
+}
+
+/// Steals the voices with the lowest velocity, or the oldest ones on ties.
+/// This is the default policy.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StealQuietest;
+
+impl VoiceStealPolicy for StealQuietest {
+    fn choose(&self, groups: &[VoiceGroupInfo]) -> Option<usize> {
+        lowest_by(groups, |group| group.velocity)
+    }
+}
+
+/// Steals the voices with the lowest current amplitude envelope level, such as
+/// voices that decayed or are almost done releasing
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StealLowestLevel;
+
+impl VoiceStealPolicy for StealLowestLevel {
+    fn choose(&self, groups: &[VoiceGroupInfo]) -> Option<usize> {
+        lowest_by(groups, |group| group.level)
+    }
+}
+
+/// Steals the oldest releasing voices, and the oldest voices if none are releasing
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StealReleasingFirst;
+
+impl VoiceStealPolicy for StealReleasingFirst {
+    fn choose(&self, groups: &[VoiceGroupInfo]) -> Option<usize> {
+        groups
+            .iter()
+            .position(|group| group.releasing)
+            .or_else(|| StealOldest.choose(groups))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_steal_policies() {
+        let group = |id, velocity, releasing, level| VoiceGroupInfo {
+            id,
+            velocity,
+            releasing,
+            level,
+        };
+        let groups = [
+            group(1, 100, false, 0.8),
+            group(2, 40, false, 0.9),
+            group(3, 90, true, 0.1),
+            group(4, 40, true, 0.5),
+        ];
+
+        assert_eq!(StealOldest.choose(&groups), Some(0));
+        assert_eq!(StealQuietest.choose(&groups), Some(1));
+        assert_eq!(StealLowestLevel.choose(&groups), Some(2));
+        assert_eq!(StealReleasingFirst.choose(&groups), Some(2));
+        assert_eq!(StealReleasingFirst.choose(&groups[..2]), Some(0));
+
+        assert_eq!(StealQuietest.choose(&[]), None);
+        assert_eq!(StealReleasingFirst.choose(&[]), None);
+    }
+}
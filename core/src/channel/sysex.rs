@@ -0,0 +1,68 @@
+/// Parses a GS or XG SysEx message that switches a part between an instrument
+/// and a drum kit. The message may include its 0xF0 and 0xF7 bytes.
+///
+/// Returns the MIDI channel of the part (0 to 15), and whether it plays drums.
+/// Such messages address a part of the synth rather than the channel they are
+/// sent on, so they should be routed to that channel as
+/// [`ChannelEvent::SetPercussionMode`](super::ChannelEvent::SetPercussionMode).
+pub fn parse_drum_part_sysex(message: &[u8]) -> Option<(u8, bool)> {
+    let message = message.strip_prefix(&[0xF0]).unwrap_or(message);
+    let message = message.strip_suffix(&[0xF7]).unwrap_or(message);
+
+    match *message {
+        // GS "use for rhythm part", with the part's block in the middle address byte
+        [0x41, _, 0x42, 0x12, 0x40, block, 0x15, map, checksum] if block & 0xF0 == 0x10 => {
+            let sum = [0x40, block, 0x15, map, checksum]
+                .iter()
+                .fold(0u32, |sum, byte| sum + *byte as u32);
+            if sum & 0x7F != 0 {
+                return None;
+            }
+
+            // Block 0 is part 10, which the other parts are numbered around
+            let channel = match block & 0x0F {
+                0 => 9,
+                part @ 1..=9 => part - 1,
+                part => part,
+            };
+            Some((channel, map != 0))
+        }
+        // XG "part mode", for the part in the middle address byte
+        [0x43, device, 0x4C, 0x08, part, 0x07, mode] if device & 0xF0 == 0x10 && part < 16 => {
+            Some((part, mode != 0))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drum_part_sysex() {
+        // GS: part 10 off, then part 2 on (which is channel 1)
+        let gs_off = [
+            0xF0, 0x41, 0x10, 0x42, 0x12, 0x40, 0x10, 0x15, 0x00, 0x1B, 0xF7,
+        ];
+        assert_eq!(parse_drum_part_sysex(&gs_off), Some((9, false)));
+        let gs_on = [
+            0xF0, 0x41, 0x10, 0x42, 0x12, 0x40, 0x12, 0x15, 0x01, 0x18, 0xF7,
+        ];
+        assert_eq!(parse_drum_part_sysex(&gs_on), Some((1, true)));
+
+        // A bad checksum is ignored
+        let gs_bad = [
+            0xF0, 0x41, 0x10, 0x42, 0x12, 0x40, 0x12, 0x15, 0x01, 0x19, 0xF7,
+        ];
+        assert_eq!(parse_drum_part_sysex(&gs_bad), None);
+
+        // XG: part 16 (channel 15) to drum setup 1, without the framing bytes
+        let xg = [0x43, 0x10, 0x4C, 0x08, 0x0F, 0x07, 0x02];
+        assert_eq!(parse_drum_part_sysex(&xg), Some((15, true)));
+
+        // GM System On isn't a drum part message
+        let gm_on = [0xF0, 0x7E, 0x7F, 0x09, 0x01, 0xF7];
+        assert_eq!(parse_drum_part_sysex(&gm_on), None);
+    }
+}
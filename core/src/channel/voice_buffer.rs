@@ -1,4 +1,7 @@
 use crate::voice::Voice;
+
+use super::steal_policy::{VoiceGroupInfo, VoiceStealPolicy};
+
 use std::{
     collections::VecDeque,
     ops::{Deref, DerefMut},
@@ -43,77 +46,81 @@ impl VoiceBuffer {
         self.id_counter
     }
 
-    fn pop_quietest_voice_group(&mut self, reference_vel: u8, ignored_id: usize) {
-        if self.buffer.len() == 0 {
-            return;
-        }
-
-        let mut quietest = reference_vel;
-        let mut quietest_index = 0;
-        let mut quietest_id = 0;
-        let mut count = 0;
-        for i in 0..self.buffer.len() {
-            let voice = &self.buffer[i];
-            if voice.id == ignored_id {
+    /// The groups with voices that aren't fading out already, from the oldest
+    fn stealable_groups(&self, ignored_id: usize) -> Vec<VoiceGroupInfo> {
+        let mut groups: Vec<VoiceGroupInfo> = Vec::new();
+        for voice in self.buffer.iter() {
+            if voice.id == ignored_id || voice.is_killed() {
                 continue;
             }
-            let vel = voice.velocity();
-            if quietest_id == voice.id {
-                count += 1;
-            } else if vel < quietest || i == 0 {
-                quietest = vel;
-                quietest_index = i;
-                quietest_id = voice.id;
-                count = 1;
-            }
-        }
 
-        if count > 0 {
-            self.buffer.drain(quietest_index..(quietest_index + count));
+            match groups.last_mut() {
+                Some(group) if group.id == voice.id => {
+                    group.releasing &= voice.is_releasing();
+                    group.level = group.level.max(voice.envelope_level());
+                }
+                _ => groups.push(VoiceGroupInfo {
+                    id: voice.id,
+                    velocity: voice.velocity(),
+                    releasing: voice.is_releasing(),
+                    level: voice.envelope_level(),
+                }),
+            }
         }
+        groups
     }
 
+    /// Pushes a group of voices, returning the id of the group. If there are more
+    /// than `max_voices` voices, groups picked by the steal policy are faded out
+    /// over `fade_samples` samples until there is room for the new ones.
     pub fn push_voices(
         &mut self,
-        vel: u8,
         voices: impl Iterator<Item = Box<dyn Voice>>,
         max_voices: Option<usize>,
-    ) {
+        steal_policy: &dyn VoiceStealPolicy,
+        fade_samples: usize,
+    ) -> usize {
         let id = self.get_id();
         for voice in voices {
             self.buffer.push_back(GroupVoice { id, voice });
         }
 
-        if let Some(max_voices) = max_voices {
-            while self.buffer.len() > max_voices {
-                self.pop_quietest_voice_group(vel, id);
+        let max_voices = match max_voices {
+            Some(max_voices) => max_voices,
+            None => return id,
+        };
+
+        // Voices fading out are on their way out, so they don't take up room
+        let mut active = self.buffer.iter().filter(|v| !v.is_killed()).count();
+        while active > max_voices {
+            let groups = self.stealable_groups(id);
+            let stolen = match steal_policy.choose(&groups).and_then(|i| groups.get(i)) {
+                Some(group) => group.id,
+                None => break,
+            };
+
+            for voice in self.buffer.iter_mut() {
+                if voice.id == stolen && !voice.is_killed() {
+                    voice.signal_kill(fade_samples);
+                    active -= 1;
+                }
             }
         }
-    }
 
-    pub fn release_next_voice(&mut self) -> Option<u8> {
-        let mut id: Option<usize> = None;
-        let mut vel = None;
+        id
+    }
 
-        // Find the first non releasing voice, get its id and release all voices with that id
+    /// Releases all voices of a group. Groups that were already removed are ignored.
+    pub fn release_voice_group(&mut self, id: usize) {
         for voice in self.buffer.iter_mut() {
-            if voice.is_releasing() {
-                continue;
-            }
-
-            if id.is_none() {
-                id = Some(voice.id);
-                vel = Some(voice.velocity())
+            if voice.id == id && !voice.is_releasing() {
+                voice.signal_release();
             }
-
-            if id != Some(voice.id) {
-                break;
-            }
-
-            voice.signal_release();
         }
+    }
 
-        vel
+    pub fn clear(&mut self) {
+        self.buffer.clear();
     }
 
     pub fn remove_ended_voices(&mut self) {
@@ -5,6 +5,8 @@ use crate::voice::{Voice, VoiceControlData};
 pub struct VoiceSpawnerMatrix {
     voice_spawners_attack: Vec<Vec<Box<dyn VoiceSpawner>>>,
     voice_spawners_release: Vec<Vec<Box<dyn VoiceSpawner>>>,
+    /// The choke groups of the attack voices at each key and velocity
+    choke_groups: Vec<Vec<u32>>,
 }
 
 fn voice_iter_from_vec<'a>(
@@ -18,18 +20,22 @@ impl VoiceSpawnerMatrix {
     pub fn new() -> Self {
         let mut voice_spawners_attack = Vec::new();
         let mut voice_spawners_release = Vec::new();
+        let mut choke_groups = Vec::new();
 
         for _ in 0..(128 * 128) {
             voice_spawners_attack.push(Vec::new());
             voice_spawners_release.push(Vec::new());
+            choke_groups.push(Vec::new());
         }
 
         voice_spawners_attack.shrink_to_fit();
         voice_spawners_release.shrink_to_fit();
+        choke_groups.shrink_to_fit();
 
         VoiceSpawnerMatrix {
             voice_spawners_attack,
             voice_spawners_release,
+            choke_groups,
         }
     }
 
@@ -69,13 +75,29 @@ impl VoiceSpawnerMatrix {
         control: &'a VoiceControlData,
         key: u8,
         vel: u8,
+        held_time: f32,
     ) -> impl Iterator<Item = Box<dyn Voice>> + 'a {
-        voice_iter_from_vec(self.get_release_spawners_vec_at(key, vel), control)
+        self.get_release_spawners_vec_at(key, vel)
+            .iter()
+            .map(move |voice| voice.spawn_release_voice(control, held_time))
+    }
+
+    /// The choke groups of the voices spawned by a note, which stop the voices
+    /// of the channel with a matching `off_by` group
+    #[inline(always)]
+    pub fn choke_groups_at(&self, key: u8, vel: u8) -> &[u32] {
+        &self.choke_groups[self.get_spawners_index_at_attack(key, vel)]
     }
 
     #[inline(always)]
     pub fn set_spawners_attack(&mut self, key: u8, vel: u8, spawners: Vec<Box<dyn VoiceSpawner>>) {
         let index = self.get_spawners_index_at_attack(key, vel);
+
+        let mut choke_groups: Vec<u32> = spawners.iter().filter_map(|s| s.choke_group()).collect();
+        choke_groups.sort_unstable();
+        choke_groups.dedup();
+
+        self.choke_groups[index] = choke_groups;
         self.voice_spawners_attack[index] = spawners;
     }
 
@@ -17,5 +17,7 @@ pub mod effects;
 
 pub mod helpers;
 
+pub mod tuning;
+
 mod threaded_ref_cell;
 use self::threaded_ref_cell::*;
@@ -1,32 +1,109 @@
-use std::{marker::PhantomData, path::PathBuf, sync::Arc};
+use std::{marker::PhantomData, sync::Arc};
 
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use simdeez::Simd;
-use to_vec::ToVec;
-
-use self::audio::AudioFileLoader;
 
 use super::{
     voice::VoiceControlData,
     voice::{
-        BufferSamplers, EnvelopeParameters, SIMDConstant, SIMDNearestSampleGrabber,
-        SIMDStereoVoice, SIMDStereoVoiceSampler, SIMDVoiceControl, SIMDVoiceEnvelope, SampleReader,
-        Voice, VoiceBase, VoiceCombineSIMD,
+        BufferSamplers, EnvelopeDescriptor, EnvelopeModulation, EnvelopeParameters,
+        FilterParameters, LfoParameters, LoopMode, SIMDConstant, SIMDSampleGrabbers,
+        SIMDSampleMono, SIMDSampleStereo, SIMDStereoConstant, SIMDStereoVoice,
+        SIMDStereoVoiceSampler, SIMDVoiceEnvelope, SIMDVoiceFilter, SIMDVoiceGenerator,
+        SIMDVoiceLFO, SIMDVoiceModulator, SIMDVoiceRamp, SampleReader, SampleReaderParams,
+        SincTaps, VelocityCurve, Voice, VoiceBase, VoiceCombineSIMD,
     },
 };
-use crate::{helpers::FREQS, voice::EnvelopeDescriptor, AudioStreamParams};
+use crate::{helpers::FREQS, tuning::Tuning, AudioStreamParams};
 
 pub mod audio;
 
+mod error;
+pub use error::*;
+
+mod sample_set;
+pub use sample_set::*;
+
+mod sf2;
+pub use sf2::*;
+
+mod sfz;
+pub use sfz::*;
+
 pub trait VoiceSpawner: Sync + Send {
     fn spawn_voice(&self, control: &VoiceControlData) -> Box<dyn Voice>;
+
+    /// Spawns a release voice, for a note that was held for `held_time` seconds
+    fn spawn_release_voice(&self, control: &VoiceControlData, _held_time: f32) -> Box<dyn Voice> {
+        self.spawn_voice(control)
+    }
+
+    /// The choke group of the spawned voices. Playing them stops the voices
+    /// of the channel with a matching `off_by` group.
+    fn choke_group(&self) -> Option<u32> {
+        None
+    }
+}
+
+/// How samples are interpolated when they are played back at a different speed.
+/// The options are ordered from the cheapest to the highest quality.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolator {
+    #[default]
+    Nearest,
+    Linear,
+    /// Catmull-Rom spline interpolation over 4 samples
+    Cubic,
+    /// Windowed sinc interpolation over the given number of samples
+    Sinc(SincTaps),
+}
+
+/// How the pressure on a key (channel pressure or polyphonic aftertouch)
+/// modulates its voices, with each depth applying at full pressure.
+/// Voices only pay for the modulations with a depth.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PressureModulation {
+    /// The volume change, in decibels
+    pub volume: f32,
+    /// The filter cutoff change, in cents. Only applies to filtered voices.
+    pub cutoff: f32,
+    /// The depth of the vibrato, in cents
+    pub vibrato: f32,
+}
+
+impl PressureModulation {
+    /// The routing of the SF2 default modulators, where the channel pressure
+    /// adds up to 50 cents of vibrato
+    pub fn sf2_default() -> Self {
+        PressureModulation {
+            vibrato: 50.0,
+            ..Default::default()
+        }
+    }
 }
 
 pub trait SoundfontBase: Sync + Send + std::fmt::Debug {
     fn stream_params<'a>(&'a self) -> &'a AudioStreamParams;
 
-    fn get_attack_voice_spawners_at(&self, key: u8, vel: u8) -> Vec<Box<dyn VoiceSpawner>>;
-    fn get_release_voice_spawners_at(&self, key: u8, vel: u8) -> Vec<Box<dyn VoiceSpawner>>;
+    /// Whether the soundfont has an instrument for the bank and program.
+    /// Soundfonts without presets play the same instrument for all of them.
+    fn has_preset(&self, _bank: u8, _preset: u8) -> bool {
+        true
+    }
+
+    fn get_attack_voice_spawners_at(
+        &self,
+        bank: u8,
+        preset: u8,
+        key: u8,
+        vel: u8,
+    ) -> Vec<Box<dyn VoiceSpawner>>;
+    fn get_release_voice_spawners_at(
+        &self,
+        bank: u8,
+        preset: u8,
+        key: u8,
+        vel: u8,
+    ) -> Vec<Box<dyn VoiceSpawner>>;
 }
 
 // pub struct SineVoice {
@@ -73,151 +150,542 @@ pub trait SoundfontBase: Sync + Send + std::fmt::Debug {
 //     }
 // }
 
+/// A modulation envelope routed to a voice parameter, such as the pitch or the filter cutoff
+#[derive(Debug, Clone)]
+struct ModulationEnvelope {
+    params: Arc<EnvelopeParameters>,
+    depth: f32, // Cents at the envelope's peak
+}
+
+impl ModulationEnvelope {
+    /// Creates the routing, or `None` if the depth is zero and the envelope would have no effect
+    fn new(params: Arc<EnvelopeParameters>, depth: f32) -> Option<Self> {
+        if depth == 0.0 {
+            None
+        } else {
+            Some(ModulationEnvelope { params, depth })
+        }
+    }
+
+    /// A generator of the multiplier to apply to the modulated parameter
+    fn multiplier<S: Simd>(&self) -> impl SIMDVoiceGenerator<S, SIMDSampleMono<S>> {
+        let depth = SIMDConstant::<S>::new(self.depth);
+        let envelope = SIMDVoiceEnvelope::new(self.params.clone());
+        let cents = VoiceCombineSIMD::mult(depth, envelope);
+        VoiceCombineSIMD::cents_to_multiplier(cents)
+    }
+}
+
+/// An envelope whose stage times depend on the key and velocity of each voice
+#[derive(Debug)]
+struct ScaledEnvelope {
+    descriptor: EnvelopeDescriptor,
+    modulation: EnvelopeModulation,
+    params: Arc<EnvelopeParameters>, // The unmodulated parameters
+    sample_rate: u32,
+}
+
+impl ScaledEnvelope {
+    fn new(
+        descriptor: EnvelopeDescriptor,
+        modulation: EnvelopeModulation,
+        sample_rate: u32,
+    ) -> Self {
+        ScaledEnvelope {
+            descriptor,
+            modulation,
+            params: Arc::new(descriptor.to_envelope_params(sample_rate)),
+            sample_rate,
+        }
+    }
+
+    /// Resolves the envelope for a key and velocity. This is only done when the
+    /// voice spawners are built, so voices don't compute their own parameters.
+    fn params_at(&self, key: u8, vel: u8) -> Arc<EnvelopeParameters> {
+        if self.modulation.is_empty() {
+            self.params.clone()
+        } else {
+            let descriptor = self.descriptor.modulated(&self.modulation, key, vel);
+            Arc::new(descriptor.to_uncached_envelope_params(self.sample_rate))
+        }
+    }
+}
+
+/// The pitch that a key plays samples at, relative to the key they were recorded at
+#[derive(Debug, Clone, Copy)]
+struct SamplePitch {
+    key: u8,
+    root_key: u8,
+    scale_tuning: f32, // Cents per key
+    tune: f32,         // Cents
+}
+
+impl SamplePitch {
+    fn new(key: u8, root_key: u8) -> Self {
+        SamplePitch {
+            key,
+            root_key,
+            scale_tuning: 100.0,
+            tune: 0.0,
+        }
+    }
+
+    /// The playback speed of the samples, with the key at its frequency in the
+    /// channel's tuning, or in equal temperament if the channel has none
+    fn speed(&self, tuning: Option<&Tuning>) -> f32 {
+        let frequency = match tuning {
+            Some(tuning) => tuning.frequency(self.key),
+            None => FREQS[self.key as usize],
+        };
+        let ratio = frequency / FREQS[self.root_key as usize];
+        ratio.powf(self.scale_tuning / 100.0) * 2.0f32.powf(self.tune / 1200.0)
+    }
+}
+
 struct SampledVoiceSpawner<S: 'static + Simd + Send + Sync> {
-    base_freq: f32,
-    amp: f32,
+    pitch: SamplePitch,
+    amp_left: f32,
+    amp_right: f32,
+    velocity_gain: f32,
     volume_envelope_params: Arc<EnvelopeParameters>,
     samples: Vec<Arc<[f32]>>,
+    sample_params: SampleReaderParams,
+    release_decay: f32,
+    interpolator: Interpolator,
+    filter: Option<FilterParameters>,
+    pitch_envelope: Option<ModulationEnvelope>,
+    filter_envelope: Option<ModulationEnvelope>,
+    choke_group: Option<u32>,
+    off_by: Option<u32>,
+    pressure_modulation: PressureModulation,
+    mod_wheel_vibrato: f32,
+    vibrato_lfo: Option<LfoParameters>,
     vel: u8,
     _s: PhantomData<S>,
 }
 
 impl<S: Simd + Send + Sync> SampledVoiceSpawner<S> {
+    /// Creates a spawner for a single velocity.
+    ///
+    /// `pitch` sets the playback speed of the samples, `gain` is applied on top
+    /// of the velocity amplitude and `pan` ranges from -1 (left) to 1 (right).
+    /// The velocity amplitude follows the default [`VelocityCurve`], unless another
+    /// curve is set through `with_velocity_curve` or by the channel.
+    /// Mono samples are played on both channels, and all channels share the same
+    /// playback region and loop points.
     pub fn new(
-        key: u8,
         vel: u8,
-        sample_rate_fac: f32,
+        pitch: SamplePitch,
+        gain: f32,
+        pan: f32,
         volume_envelope_params: Arc<EnvelopeParameters>,
-        sf: &SquareSoundfont,
+        samples: Vec<Arc<[f32]>>,
+        sample_params: SampleReaderParams,
     ) -> Self {
-        let amp = 1.04f32.powf(vel as f32 - 127.0);
-
-        let (samples, base_freq) = if key < 21 {
-            let samples = sf.samples[0].clone();
-            let base_freq = FREQS[key as usize] / FREQS[21];
-            (samples, base_freq * sample_rate_fac)
-        } else if key > 108 {
-            let samples = sf.samples.last().unwrap().clone();
-            let base_freq = FREQS[key as usize] / FREQS[108];
-            (samples, base_freq * sample_rate_fac)
-        } else {
-            let samples = sf.samples[key as usize - 21].clone();
-            let base_freq = 1.0;
-            (samples, base_freq * sample_rate_fac)
-        };
+        let pan = pan.clamp(-1.0, 1.0);
 
         Self {
-            base_freq,
-            amp,
+            pitch,
+            amp_left: gain * (1.0 - pan).min(1.0),
+            amp_right: gain * (1.0 + pan).min(1.0),
+            velocity_gain: VelocityCurve::default().gain(vel),
             volume_envelope_params,
             samples,
+            sample_params,
+            release_decay: 0.0,
+            interpolator: Interpolator::default(),
+            filter: None,
+            pitch_envelope: None,
+            filter_envelope: None,
+            choke_group: None,
+            off_by: None,
+            pressure_modulation: PressureModulation::default(),
+            mod_wheel_vibrato: 0.0,
+            vibrato_lfo: None,
             vel,
             _s: PhantomData,
         }
     }
-}
 
-impl<S: 'static + Sync + Send + Simd> VoiceSpawner for SampledVoiceSpawner<S> {
-    fn spawn_voice(&self, control: &VoiceControlData) -> Box<dyn Voice> {
-        let pitch_fac = SIMDConstant::<S>::new(self.base_freq as f32);
+    /// Sets the curve mapping the velocity to the amplitude
+    pub fn with_velocity_curve(mut self, velocity_curve: &VelocityCurve) -> Self {
+        self.velocity_gain = velocity_curve.gain(self.vel);
+        self
+    }
+
+    /// Sets how much release voices are attenuated by, in decibels per second
+    /// that the note was held for.
+    pub fn with_release_decay(mut self, release_decay: f32) -> Self {
+        self.release_decay = release_decay;
+        self
+    }
 
-        let pitch_multiplier = SIMDVoiceControl::new(control, |vc| vc.voice_pitch_multiplier);
+    /// Sets how the samples are interpolated
+    pub fn with_interpolator(mut self, interpolator: Interpolator) -> Self {
+        self.interpolator = interpolator;
+        self
+    }
 
-        let pitch_fac = VoiceCombineSIMD::mult(pitch_fac, pitch_multiplier);
+    /// Sets the filter applied to the samples, if any
+    pub fn with_filter(mut self, filter: Option<FilterParameters>) -> Self {
+        self.filter = filter;
+        self
+    }
 
-        let left = SIMDNearestSampleGrabber::new(SampleReader::new(BufferSamplers::new_f32(
-            self.samples[0].clone(),
-        )));
-        let right = SIMDNearestSampleGrabber::new(SampleReader::new(BufferSamplers::new_f32(
-            self.samples[1].clone(),
-        )));
+    /// Sets the modulation envelopes of the pitch and of the filter cutoff, if any
+    pub fn with_modulation_envelopes(
+        mut self,
+        pitch_envelope: Option<ModulationEnvelope>,
+        filter_envelope: Option<ModulationEnvelope>,
+    ) -> Self {
+        self.pitch_envelope = pitch_envelope;
+        self.filter_envelope = filter_envelope;
+        self
+    }
+
+    /// Sets the choke group of the voices, and the group whose notes stop them
+    pub fn with_choke_groups(mut self, choke_group: Option<u32>, off_by: Option<u32>) -> Self {
+        self.choke_group = choke_group;
+        self.off_by = off_by;
+        self
+    }
+
+    /// Sets how the key pressure modulates the voices, along with the LFO of the
+    /// pressure vibrato
+    pub fn with_pressure_modulation(
+        mut self,
+        pressure_modulation: PressureModulation,
+        vibrato_lfo: LfoParameters,
+    ) -> Self {
+        self.pressure_modulation = pressure_modulation;
+        self.vibrato_lfo = Some(vibrato_lfo);
+        self
+    }
+
+    /// Sets the depth of the vibrato at full mod wheel (CC1) in cents, along
+    /// with its LFO, which is shared with the pressure vibrato
+    pub fn with_mod_wheel_vibrato(mut self, depth: f32, vibrato_lfo: LfoParameters) -> Self {
+        self.mod_wheel_vibrato = depth;
+        self.vibrato_lfo = Some(vibrato_lfo);
+        self
+    }
+
+    fn sample_grabber(&self, sample: &Arc<[f32]>) -> SIMDSampleGrabbers<S, BufferSamplers> {
+        let reader = SampleReader::new_with_params(
+            BufferSamplers::new_f32(sample.clone()),
+            &self.sample_params,
+        );
+        match self.interpolator {
+            Interpolator::Nearest => SIMDSampleGrabbers::nearest(reader),
+            Interpolator::Linear => SIMDSampleGrabbers::linear(reader),
+            Interpolator::Cubic => SIMDSampleGrabbers::cubic(reader),
+            Interpolator::Sinc(taps) => SIMDSampleGrabbers::sinc(reader, taps),
+        }
+    }
+
+    fn spawn_voice_with_gain(&self, control: &VoiceControlData, gain: f32) -> Box<dyn Voice> {
+        let velocity_gain = match &control.velocity_curve {
+            Some(curve) => curve.gain(self.vel),
+            None => self.velocity_gain,
+        };
+        let gain = gain * velocity_gain;
+
+        let pitch_fac = SIMDVoiceModulator::new(control, self.pitch, |vc, pitch| {
+            pitch.speed(vc.tuning.as_deref()) * vc.voice_pitch_multiplier
+        });
+
+        // Only voices that can glide pay for the ramp of their pitch offset
+        if control.mono || control.glide_from != 0.0 {
+            let glide = SIMDVoiceRamp::new(control.glide_from, control, |vc| {
+                (vc.pitch_offset, vc.portamento_rate)
+            });
+            let glide = VoiceCombineSIMD::semitones_to_multiplier(glide);
+            let pitch_fac = VoiceCombineSIMD::mult(pitch_fac, glide);
+            self.build_vibrato(pitch_fac, control, gain)
+        } else {
+            self.build_vibrato(pitch_fac, control, gain)
+        }
+    }
+
+    /// Applies the vibrato of the mod wheel and of the key pressure to the pitch
+    /// generator, if either has a depth
+    fn build_vibrato<Pitch>(
+        &self,
+        pitch_fac: Pitch,
+        control: &VoiceControlData,
+        gain: f32,
+    ) -> Box<dyn Voice>
+    where
+        Pitch: 'static + SIMDVoiceGenerator<S, SIMDSampleMono<S>>,
+    {
+        let depths = (self.mod_wheel_vibrato, self.pressure_modulation.vibrato);
+        let lfo = match self.vibrato_lfo {
+            Some(lfo) if depths != (0.0, 0.0) => lfo,
+            _ => return self.build_pitch_envelope(pitch_fac, control, gain),
+        };
+
+        let lfo = SIMDVoiceLFO::new(&lfo);
+        let depth = SIMDVoiceModulator::new(control, depths, |vc, (modulation, pressure)| {
+            vc.modulation * modulation + vc.pressure() * pressure
+        });
+        let vibrato = VoiceCombineSIMD::cents_to_multiplier(VoiceCombineSIMD::mult(lfo, depth));
+        let pitch_fac = VoiceCombineSIMD::mult(pitch_fac, vibrato);
+        self.build_pitch_envelope(pitch_fac, control, gain)
+    }
+
+    /// Applies the pitch envelope to the pitch generator, if there is one
+    fn build_pitch_envelope<Pitch>(
+        &self,
+        pitch_fac: Pitch,
+        control: &VoiceControlData,
+        gain: f32,
+    ) -> Box<dyn Voice>
+    where
+        Pitch: 'static + SIMDVoiceGenerator<S, SIMDSampleMono<S>>,
+    {
+        match &self.pitch_envelope {
+            Some(envelope) => {
+                let pitch_fac = VoiceCombineSIMD::mult(pitch_fac, envelope.multiplier());
+                self.build_sampler(pitch_fac, control, gain)
+            }
+            None => self.build_sampler(pitch_fac, control, gain),
+        }
+    }
+
+    /// Builds the sampler for the pitch generator, along with its filter
+    fn build_sampler<Pitch>(
+        &self,
+        pitch_fac: Pitch,
+        control: &VoiceControlData,
+        gain: f32,
+    ) -> Box<dyn Voice>
+    where
+        Pitch: 'static + SIMDVoiceGenerator<S, SIMDSampleMono<S>>,
+    {
+        let left_sample = &self.samples[0];
+        let right_sample = self.samples.get(1).unwrap_or(left_sample);
+
+        let left = self.sample_grabber(left_sample);
+        let right = self.sample_grabber(right_sample);
 
         let sampler = SIMDStereoVoiceSampler::new(left, right, pitch_fac);
 
-        let amp = SIMDConstant::<S>::new(self.amp);
+        let filter = match self.filter {
+            Some(filter) => filter,
+            None => return self.build_voice(sampler, control, gain),
+        };
+
+        let cutoff = SIMDConstant::<S>::new(filter.cutoff);
+        let depth = self.pressure_modulation.cutoff;
+        if depth != 0.0 {
+            let pressure = SIMDVoiceModulator::new(control, depth, |vc, depth| {
+                2.0f32.powf(vc.pressure() * depth / 1200.0)
+            });
+            let cutoff = VoiceCombineSIMD::mult(cutoff, pressure);
+            self.build_filter(&filter, sampler, cutoff, control, gain)
+        } else {
+            self.build_filter(&filter, sampler, cutoff, control, gain)
+        }
+    }
+
+    /// Filters the sampler, applying the filter envelope to the cutoff if there is one
+    fn build_filter<Gen, Cutoff>(
+        &self,
+        filter: &FilterParameters,
+        sampler: Gen,
+        cutoff: Cutoff,
+        control: &VoiceControlData,
+        gain: f32,
+    ) -> Box<dyn Voice>
+    where
+        Gen: 'static + SIMDVoiceGenerator<S, SIMDSampleStereo<S>>,
+        Cutoff: 'static + SIMDVoiceGenerator<S, SIMDSampleMono<S>>,
+    {
+        match &self.filter_envelope {
+            Some(envelope) => {
+                let cutoff = VoiceCombineSIMD::mult(cutoff, envelope.multiplier());
+                let filtered = SIMDVoiceFilter::new(filter, sampler, cutoff);
+                self.build_voice(filtered, control, gain)
+            }
+            None => {
+                let filtered = SIMDVoiceFilter::new(filter, sampler, cutoff);
+                self.build_voice(filtered, control, gain)
+            }
+        }
+    }
+
+    /// Applies the amplitude, the volume envelope and the pressure volume to the sampler
+    fn build_voice<Gen>(
+        &self,
+        sampler: Gen,
+        control: &VoiceControlData,
+        gain: f32,
+    ) -> Box<dyn Voice>
+    where
+        Gen: 'static + SIMDVoiceGenerator<S, SIMDSampleStereo<S>>,
+    {
+        let amp = SIMDStereoConstant::<S>::new(self.amp_left * gain, self.amp_right * gain);
         let volume_envelope = SIMDVoiceEnvelope::new(self.volume_envelope_params.clone());
 
         let modulated = VoiceCombineSIMD::mult(amp, sampler);
         let modulated = VoiceCombineSIMD::mult(volume_envelope, modulated);
 
-        let flattened = SIMDStereoVoice::new(modulated);
-        let base = VoiceBase::new(self.vel, flattened);
-
-        Box::new(base)
+        let depth = self.pressure_modulation.volume;
+        if depth != 0.0 {
+            let pressure = SIMDVoiceModulator::new(control, depth, |vc, depth| {
+                10.0f32.powf(vc.pressure() * depth / 20.0)
+            });
+            let modulated = VoiceCombineSIMD::mult(pressure, modulated);
+            self.wrap_voice(modulated, control)
+        } else {
+            self.wrap_voice(modulated, control)
+        }
     }
-}
 
-#[derive(Debug)]
-pub struct SquareSoundfont {
-    samples: Vec<Vec<Arc<[f32]>>>,
-    volume_envelope_params: Arc<EnvelopeParameters>,
-    stream_params: AudioStreamParams,
+    /// Flattens the generator into the voice, which ignores note offs if it is a one shot
+    fn wrap_voice<Gen>(&self, generator: Gen, control: &VoiceControlData) -> Box<dyn Voice>
+    where
+        Gen: 'static + SIMDVoiceGenerator<S, SIMDSampleStereo<S>>,
+    {
+        let flattened = SIMDStereoVoice::new(generator);
+
+        // Drums without a loop play to the end, as their note offs often come right away
+        let one_shot = match self.sample_params.loop_mode {
+            LoopMode::OneShot => true,
+            LoopMode::NoLoop => control.percussion,
+            _ => false,
+        };
+        let voice = if one_shot {
+            VoiceBase::new_one_shot(self.vel, flattened)
+        } else {
+            VoiceBase::new(self.vel, flattened)
+        };
+        Box::new(voice.with_off_by(self.off_by))
+    }
 }
 
-impl SquareSoundfont {
-    pub fn new(sample_rate: u32, channels: u16) -> Self {
-        let samples = (21..109).to_vec().par_iter()
-            .map(|i| {
-                println!("Loading {}", i);
-                AudioFileLoader::load_wav(&PathBuf::from(format!(
-                    "D:/Midis/Steinway-B-211-master/Steinway-B-211-master/Samples/KEPSREC{:0>3}.wav",
-                    i
-                )))
-                .unwrap()
-            })
-            .collect();
-
-        let envelope_descriptor = EnvelopeDescriptor {
-            start_percent: 0.0,
-            delay: 0.0,
-            attack: 0.0,
-            hold: 0.0,
-            decay: 0.1,
-            sustain_percent: 0.7,
-            release: 0.2,
-        };
+impl<S: 'static + Sync + Send + Simd> VoiceSpawner for SampledVoiceSpawner<S> {
+    fn spawn_voice(&self, control: &VoiceControlData) -> Box<dyn Voice> {
+        self.spawn_voice_with_gain(control, 1.0)
+    }
 
-        let volume_envelope_params = Arc::new(envelope_descriptor.to_envelope_params(sample_rate));
+    fn spawn_release_voice(&self, control: &VoiceControlData, held_time: f32) -> Box<dyn Voice> {
+        let gain = 10.0f32.powf(-self.release_decay * held_time / 20.0);
+        self.spawn_voice_with_gain(control, gain)
+    }
 
-        Self {
-            samples,
-            volume_envelope_params,
-            stream_params: AudioStreamParams::new(sample_rate, channels),
-        }
+    fn choke_group(&self) -> Option<u32> {
+        self.choke_group
     }
 }
 
-impl SoundfontBase for SquareSoundfont {
-    fn stream_params<'a>(&'a self) -> &'a AudioStreamParams {
-        &self.stream_params
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use simdeez::*; // nuts
 
-    fn get_attack_voice_spawners_at(&self, key: u8, vel: u8) -> Vec<Box<dyn VoiceSpawner>> {
-        use simdeez::*; // nuts
+    use simdeez::avx2::*;
+    use simdeez::scalar::*;
+    use simdeez::sse2::*;
+    use simdeez::sse41::*;
 
-        use simdeez::avx2::*;
-        use simdeez::scalar::*;
-        use simdeez::sse2::*;
-        use simdeez::sse41::*;
+    use crate::voice::{EnvelopeCurveType, EnvelopeDescriptor, LfoDescriptor, LfoShape};
 
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() / b < 0.0001, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_sample_pitch() {
+        let pitch = SamplePitch::new(61, 60);
+        assert_close(pitch.speed(None), FREQS[61] / FREQS[60]);
+
+        // Tuned keys play at their frequency relative to the root key's equal temperament one
+        let mut tuning = Tuning::default();
+        tuning.set_frequency(61, FREQS[60] * 1.5);
+        tuning.set_frequency(60, FREQS[60] * 1.1);
+        assert_close(pitch.speed(Some(&tuning)), 1.5);
+
+        // The scale tuning and tune apply on top of the tuning
+        let pitch = SamplePitch {
+            scale_tuning: 50.0,
+            tune: 100.0,
+            ..pitch
+        };
+        assert_close(pitch.speed(None), 2.0f32.powf(1.5 / 12.0));
+        assert_close(
+            pitch.speed(Some(&tuning)),
+            1.5f32.sqrt() * 2.0f32.powf(1.0 / 12.0),
+        );
+    }
+
+    #[test]
+    fn test_mod_wheel_vibrato() {
         simd_runtime_generate!(
-            fn get(key: u8, vel: u8, sf: &SquareSoundfont) -> Vec<Box<dyn VoiceSpawner>> {
-                let sr = 96000.0 / sf.stream_params.sample_rate as f32;
-
-                vec![Box::new(SampledVoiceSpawner::<S>::new(
-                    key,
-                    vel,
-                    sr,
-                    sf.volume_envelope_params.clone(),
-                    sf,
-                ))]
+            fn run() {
+                // The sample is a ramp, so the output follows the playback position
+                let sample: Arc<[f32]> = (0..4096).map(|i| i as f32).collect::<Vec<_>>().into();
+                let envelope = EnvelopeDescriptor {
+                    start_percent: 1.0,
+                    delay: 0.0,
+                    attack: 0.0,
+                    hold: 0.0,
+                    decay: 0.0,
+                    sustain_percent: 1.0,
+                    release: 0.0,
+                    attack_curve: EnvelopeCurveType::Linear,
+                    decay_curve: EnvelopeCurveType::Linear,
+                    release_curve: EnvelopeCurveType::Linear,
+                };
+                let lfo = LfoDescriptor {
+                    shape: LfoShape::Sine,
+                    frequency: 100.0,
+                    delay: 0.0,
+                    fade: 0.0,
+                };
+
+                let spawner = SampledVoiceSpawner::<S>::new(
+                    127,
+                    SamplePitch::new(60, 60),
+                    1.0,
+                    0.0,
+                    Arc::new(envelope.to_envelope_params(48000)),
+                    vec![sample],
+                    SampleReaderParams::default(),
+                )
+                .with_mod_wheel_vibrato(100.0, lfo.to_lfo_params(48000));
+
+                let render = |voice: &mut Box<dyn Voice>| {
+                    let mut out = vec![0.0; 512];
+                    voice.render_to(&mut out);
+                    out
+                };
+
+                let mut control = VoiceControlData::new_defaults();
+                let mut still = spawner.spawn_voice(&control);
+                let mut modulated = spawner.spawn_voice(&control);
+
+                // Without the mod wheel, the vibrato has no depth
+                assert_eq!(render(&mut still), render(&mut modulated));
+
+                // Raising the mod wheel moves the pitch of the playing voice
+                control.modulation = 1.0;
+                modulated.process_controls(&control);
+                let still_out = render(&mut still);
+                let modulated_out = render(&mut modulated);
+                assert_ne!(still_out, modulated_out);
+
+                // The pitch only moves within a semitone around the unmodulated one
+                let ratio = 2.0f32.powf(100.0 / 1200.0);
+                for (a, b) in still_out.iter().zip(modulated_out.iter()).step_by(2) {
+                    let (start_a, start_b) = (a - 256.0, b - 256.0);
+                    assert!(start_b <= start_a * ratio + 1.0);
+                    assert!(start_b >= start_a / ratio - 1.0);
+                }
             }
         );
 
-        get_runtime_select(key, vel, &self)
-    }
-
-    fn get_release_voice_spawners_at(&self, _key: u8, _vel: u8) -> Vec<Box<dyn VoiceSpawner>> {
-        vec![]
+        run_runtime_select();
     }
 }
@@ -1,3 +1,12 @@
+use std::sync::Arc;
+
+mod resample;
 pub mod wav;
 
+pub(crate) use resample::SincResampler;
+
+/// The channels of an audio file resampled to the output sample rate, along
+/// with the original sample rate of the file
+pub type LoadedAudio = (Vec<Arc<[f32]>>, u32);
+
 pub struct AudioFileLoader;
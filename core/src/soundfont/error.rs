@@ -0,0 +1,86 @@
+use std::{error::Error, fmt, io, path::PathBuf};
+
+/// An error encountered while loading a soundfont or one of its sample files.
+#[derive(Debug)]
+pub enum LoadSfError {
+    /// A file couldn't be opened or read
+    Io { path: PathBuf, error: io::Error },
+
+    /// A file was read, but its contents couldn't be parsed
+    Invalid { path: PathBuf, reason: String },
+
+    /// A file uses a format or encoding that isn't supported
+    Unsupported { path: PathBuf, reason: String },
+
+    /// The requested preset doesn't exist in the soundfont
+    PresetNotFound {
+        path: PathBuf,
+        bank: u16,
+        preset: u16,
+    },
+}
+
+impl LoadSfError {
+    pub(crate) fn io(path: impl Into<PathBuf>, error: io::Error) -> Self {
+        LoadSfError::Io {
+            path: path.into(),
+            error,
+        }
+    }
+
+    pub(crate) fn invalid(path: impl Into<PathBuf>, reason: impl Into<String>) -> Self {
+        LoadSfError::Invalid {
+            path: path.into(),
+            reason: reason.into(),
+        }
+    }
+
+    pub(crate) fn unsupported(path: impl Into<PathBuf>, reason: impl Into<String>) -> Self {
+        LoadSfError::Unsupported {
+            path: path.into(),
+            reason: reason.into(),
+        }
+    }
+
+    /// The path of the file that failed to load
+    pub fn path(&self) -> &PathBuf {
+        match self {
+            LoadSfError::Io { path, .. }
+            | LoadSfError::Invalid { path, .. }
+            | LoadSfError::Unsupported { path, .. }
+            | LoadSfError::PresetNotFound { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for LoadSfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadSfError::Io { path, error } => {
+                write!(f, "failed to read {}: {}", path.display(), error)
+            }
+            LoadSfError::Invalid { path, reason } => {
+                write!(f, "invalid file {}: {}", path.display(), reason)
+            }
+            LoadSfError::Unsupported { path, reason } => {
+                write!(f, "unsupported file {}: {}", path.display(), reason)
+            }
+            LoadSfError::PresetNotFound { path, bank, preset } => write!(
+                f,
+                "preset {} in bank {} not found in {}",
+                preset,
+                bank,
+                path.display()
+            ),
+        }
+    }
+}
+
+impl Error for LoadSfError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadSfError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
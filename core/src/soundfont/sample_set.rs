@@ -0,0 +1,287 @@
+use std::{ops::RangeInclusive, path::PathBuf, sync::Arc};
+
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use super::{
+    audio::AudioFileLoader, Interpolator, LoadSfError, SamplePitch, SampledVoiceSpawner,
+    SoundfontBase, VoiceSpawner,
+};
+use crate::{
+    voice::{
+        EnvelopeCurveType, EnvelopeDescriptor, EnvelopeParameters, SampleReaderParams,
+        VelocityCurve,
+    },
+    AudioStreamParams,
+};
+
+/// The options for loading a [`SampleSetSoundfont`].
+#[derive(Debug, Clone)]
+pub struct SampleSetOptions {
+    /// The directory containing the sample files
+    pub directory: PathBuf,
+
+    /// The file name of each sample within the directory.
+    ///
+    /// `{key}` is replaced with the sample's root key and `{layer}` with its
+    /// velocity layer, starting from 1. A zero padded width can be given after
+    /// a colon, for example `piano_{key:03}_v{layer}.wav`.
+    pub filename_template: String,
+
+    /// The keys that can be played
+    pub key_range: RangeInclusive<u8>,
+
+    /// The highest velocity of each velocity layer, in strictly ascending order.
+    /// There must be at least one layer.
+    pub velocity_layers: Vec<u8>,
+
+    /// The keys that have a sample file. Other keys in the key range play the
+    /// sample of the closest root key, repitched. If `None`, every key in the
+    /// key range has its own sample.
+    pub root_keys: Option<Vec<u8>>,
+
+    /// The volume envelope of every sample
+    pub envelope: EnvelopeDescriptor,
+
+    /// How the samples are interpolated when repitched
+    pub interpolation: Interpolator,
+
+    /// How the velocity maps onto the amplitude of the samples
+    pub velocity_curve: VelocityCurve,
+}
+
+impl SampleSetOptions {
+    /// Creates options for a single velocity layer with a sample for every key,
+    /// which can then be adjusted through the public fields.
+    pub fn new(directory: impl Into<PathBuf>, filename_template: impl Into<String>) -> Self {
+        SampleSetOptions {
+            directory: directory.into(),
+            filename_template: filename_template.into(),
+            key_range: 0..=127,
+            velocity_layers: vec![127],
+            root_keys: None,
+            envelope: EnvelopeDescriptor {
+                start_percent: 0.0,
+                delay: 0.0,
+                attack: 0.0,
+                hold: 0.0,
+                decay: 0.1,
+                sustain_percent: 0.7,
+                release: 0.2,
+                attack_curve: EnvelopeCurveType::Linear,
+                decay_curve: EnvelopeCurveType::Exponential,
+                release_curve: EnvelopeCurveType::Exponential,
+            },
+            interpolation: Interpolator::default(),
+            velocity_curve: VelocityCurve::default(),
+        }
+    }
+
+    fn root_keys(&self) -> Vec<u8> {
+        match &self.root_keys {
+            Some(keys) => keys.clone(),
+            None => self.key_range.clone().collect(),
+        }
+    }
+}
+
+/// Fills in the `{key}` and `{layer}` placeholders of a file name template
+fn format_filename(template: &str, key: u8, layer: usize) -> String {
+    let mut name = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        name.push_str(&rest[..start]);
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => {
+                rest = &rest[start..];
+                break;
+            }
+        };
+
+        let placeholder = &rest[start + 1..end];
+        let (field, width) = match placeholder.find(':') {
+            Some(colon) => (
+                &placeholder[..colon],
+                placeholder[colon + 1..].parse::<usize>().unwrap_or(0),
+            ),
+            None => (placeholder, 0),
+        };
+
+        match field {
+            "key" => name.push_str(&format!("{:0>width$}", key, width = width)),
+            "layer" => name.push_str(&format!("{:0>width$}", layer, width = width)),
+            _ => name.push_str(&rest[start..=end]),
+        }
+
+        rest = &rest[end + 1..];
+    }
+
+    name.push_str(rest);
+    name
+}
+
+/// A single sample file, played for the keys closest to its root key
+#[derive(Debug)]
+struct SampleSetSample {
+    root_key: u8,
+    samples: Vec<Arc<[f32]>>,
+}
+
+#[derive(Debug)]
+struct SampleSetLayer {
+    max_vel: u8,
+    samples: Vec<SampleSetSample>,
+}
+
+/// A soundfont built from a directory of wav files, with one file per root key
+/// and velocity layer.
+#[derive(Debug)]
+pub struct SampleSetSoundfont {
+    layers: Vec<SampleSetLayer>,
+    key_range: RangeInclusive<u8>,
+    interpolation: Interpolator,
+    velocity_curve: VelocityCurve,
+    volume_envelope_params: Arc<EnvelopeParameters>,
+    stream_params: AudioStreamParams,
+}
+
+impl SampleSetSoundfont {
+    pub fn new(
+        options: &SampleSetOptions,
+        sample_rate: u32,
+        channels: u16,
+    ) -> Result<Self, LoadSfError> {
+        let velocity_layers = &options.velocity_layers;
+        if velocity_layers.is_empty() {
+            return Err(LoadSfError::invalid(
+                &options.directory,
+                "the sample set has no velocity layers",
+            ));
+        }
+        if velocity_layers.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(LoadSfError::invalid(
+                &options.directory,
+                "the velocity layers are not in ascending order",
+            ));
+        }
+
+        let root_keys = options.root_keys();
+
+        let layers = velocity_layers
+            .iter()
+            .enumerate()
+            .map(|(layer, &max_vel)| {
+                let samples = root_keys
+                    .clone()
+                    .into_par_iter()
+                    .map(|root_key| {
+                        let filename =
+                            format_filename(&options.filename_template, root_key, layer + 1);
+                        let path = options.directory.join(filename);
+                        let (samples, _) = AudioFileLoader::load_wav(&path, sample_rate)?;
+                        Ok(SampleSetSample { root_key, samples })
+                    })
+                    .collect::<Result<_, LoadSfError>>()?;
+
+                Ok(SampleSetLayer { max_vel, samples })
+            })
+            .collect::<Result<_, LoadSfError>>()?;
+
+        Ok(Self {
+            layers,
+            key_range: options.key_range.clone(),
+            interpolation: options.interpolation,
+            velocity_curve: options.velocity_curve.clone(),
+            volume_envelope_params: Arc::new(options.envelope.to_envelope_params(sample_rate)),
+            stream_params: AudioStreamParams::new(sample_rate, channels),
+        })
+    }
+
+    /// Picks the sample closest to the key in the velocity's layer
+    fn get_sample(&self, key: u8, vel: u8) -> Option<&SampleSetSample> {
+        if !self.key_range.contains(&key) {
+            return None;
+        }
+
+        let layer = self
+            .layers
+            .iter()
+            .find(|layer| vel <= layer.max_vel)
+            .or_else(|| self.layers.last())?;
+
+        layer
+            .samples
+            .iter()
+            .min_by_key(|sample| (sample.root_key as i16 - key as i16).abs())
+    }
+}
+
+impl SoundfontBase for SampleSetSoundfont {
+    fn stream_params(&self) -> &AudioStreamParams {
+        &self.stream_params
+    }
+
+    fn get_attack_voice_spawners_at(
+        &self,
+        _bank: u8,
+        _preset: u8,
+        key: u8,
+        vel: u8,
+    ) -> Vec<Box<dyn VoiceSpawner>> {
+        use simdeez::*; // nuts
+
+        use simdeez::avx2::*;
+        use simdeez::scalar::*;
+        use simdeez::sse2::*;
+        use simdeez::sse41::*;
+
+        simd_runtime_generate!(
+            fn get(key: u8, vel: u8, sf: &SampleSetSoundfont) -> Vec<Box<dyn VoiceSpawner>> {
+                let sample = match sf.get_sample(key, vel) {
+                    Some(sample) => sample,
+                    None => return vec![],
+                };
+                let spawner = SampledVoiceSpawner::<S>::new(
+                    vel,
+                    SamplePitch::new(key, sample.root_key),
+                    1.0,
+                    0.0,
+                    sf.volume_envelope_params.clone(),
+                    sample.samples.clone(),
+                    SampleReaderParams::default(),
+                )
+                .with_interpolator(sf.interpolation)
+                .with_velocity_curve(&sf.velocity_curve);
+                vec![Box::new(spawner)]
+            }
+        );
+
+        get_runtime_select(key, vel, self)
+    }
+
+    fn get_release_voice_spawners_at(
+        &self,
+        _bank: u8,
+        _preset: u8,
+        _key: u8,
+        _vel: u8,
+    ) -> Vec<Box<dyn VoiceSpawner>> {
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_velocity_layers() {
+        for layers in [vec![], vec![64, 32, 127], vec![64, 64, 127]] {
+            let mut options = SampleSetOptions::new("samples", "{key}_{layer}.wav");
+            options.velocity_layers = layers;
+            let result = SampleSetSoundfont::new(&options, 48000, 2);
+            assert!(matches!(result, Err(LoadSfError::Invalid { .. })));
+        }
+    }
+}
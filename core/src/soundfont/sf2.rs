@@ -0,0 +1,734 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    ops::RangeInclusive,
+    path::Path,
+    sync::Arc,
+};
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use soundfont::{
+    data::{
+        generator::{GeneratorAmount, GeneratorType},
+        hydra::sample::SampleHeader,
+        SFData,
+    },
+    Preset, SoundFont2, Zone,
+};
+
+use super::{
+    audio::SincResampler, Interpolator, LoadSfError, ModulationEnvelope, PressureModulation,
+    SamplePitch, SampledVoiceSpawner, ScaledEnvelope, SoundfontBase, VoiceSpawner,
+};
+use crate::{
+    voice::{
+        EnvelopeCurveType, EnvelopeDescriptor, EnvelopeModulation, FilterDescriptor,
+        FilterParameters, FilterType, LfoDescriptor, LfoParameters, LfoShape, LoopMode,
+        SampleReaderParams, VelocityCurve,
+    },
+    AudioStreamParams,
+};
+
+/// Generators which only apply at the instrument level, or which are ranges that
+/// intersect. Preset zones add every other generator to the instrument's value.
+const NON_ADDITIVE_GENERATORS: &[GeneratorType] = &[
+    GeneratorType::KeyRange,
+    GeneratorType::VelRange,
+    GeneratorType::Instrument,
+    GeneratorType::SampleID,
+    GeneratorType::SampleModes,
+    GeneratorType::ExclusiveClass,
+    GeneratorType::OverridingRootKey,
+    GeneratorType::Keynum,
+    GeneratorType::Velocity,
+    GeneratorType::StartAddrsOffset,
+    GeneratorType::EndAddrsOffset,
+    GeneratorType::StartloopAddrsOffset,
+    GeneratorType::EndloopAddrsOffset,
+    GeneratorType::StartAddrsCoarseOffset,
+    GeneratorType::EndAddrsCoarseOffset,
+    GeneratorType::StartloopAddrsCoarseOffset,
+    GeneratorType::EndloopAddrsCoarseOffset,
+];
+
+/// Combines the instrument and preset zone values of a generator, falling back
+/// to the default when the instrument doesn't set it.
+fn combine_generator(
+    ty: GeneratorType,
+    instrument: Option<i16>,
+    preset: Option<i16>,
+    default: i16,
+) -> i16 {
+    let value = instrument.unwrap_or(default);
+    match preset {
+        Some(preset) if !NON_ADDITIVE_GENERATORS.contains(&ty) => value.saturating_add(preset),
+        _ => value,
+    }
+}
+
+/// A zone along with the global zone of its preset or instrument, if there is one.
+struct LayeredZone<'a> {
+    local: &'a Zone,
+    global: Option<&'a Zone>,
+}
+
+impl<'a> LayeredZone<'a> {
+    /// Splits a list of zones into its global zone and its layered local zones.
+    /// The global zone, if present, is the first zone and has no terminal generator.
+    fn from_zones(zones: &'a [Zone], is_global: impl Fn(&Zone) -> bool) -> Vec<Self> {
+        let (global, locals) = match zones.first() {
+            Some(first) if is_global(first) => (Some(first), &zones[1..]),
+            _ => (None, zones),
+        };
+
+        locals
+            .iter()
+            .map(|local| LayeredZone { local, global })
+            .collect()
+    }
+
+    fn get(&self, ty: GeneratorType) -> Option<&'a GeneratorAmount> {
+        let find = |zone: &'a Zone| {
+            zone.gen_list
+                .iter()
+                .find(|gen| gen.ty == ty)
+                .map(|gen| &gen.amount)
+        };
+
+        find(self.local).or_else(|| self.global.and_then(find))
+    }
+
+    fn get_i16(&self, ty: GeneratorType) -> Option<i16> {
+        self.get(ty).and_then(|amount| {
+            let unsigned = || amount.as_u16().map(|v| *v as i16);
+            amount.as_i16().copied().or_else(unsigned)
+        })
+    }
+
+    fn get_range(&self, ty: GeneratorType) -> Option<RangeInclusive<u8>> {
+        self.get(ty)
+            .and_then(|amount| amount.as_range())
+            .map(|range| range.low..=range.high)
+    }
+}
+
+/// Resolves the final generator values of an instrument zone played through a preset zone.
+struct ZoneGenerators<'a> {
+    preset: &'a LayeredZone<'a>,
+    instrument: &'a LayeredZone<'a>,
+}
+
+impl<'a> ZoneGenerators<'a> {
+    fn get_i16(&self, ty: GeneratorType, default: i16) -> i16 {
+        let instrument = self.instrument.get_i16(ty);
+        combine_generator(ty, instrument, self.preset.get_i16(ty), default)
+    }
+
+    fn get_range(&self, ty: GeneratorType) -> RangeInclusive<u8> {
+        let full = 0..=127;
+        let preset = self.preset.get_range(ty).unwrap_or(full.clone());
+        let instrument = self.instrument.get_range(ty).unwrap_or(full);
+
+        let low = *preset.start().max(instrument.start());
+        let high = *preset.end().min(instrument.end());
+        low..=high.min(127)
+    }
+
+    /// Combines a fine and a coarse (32768 sample) address offset generator
+    fn get_address_offset(&self, fine: GeneratorType, coarse: GeneratorType) -> i64 {
+        self.get_i16(fine, 0) as i64 + self.get_i16(coarse, 0) as i64 * 32768
+    }
+
+    fn sample_params(&self, header: &SampleHeader) -> SampleReaderParams {
+        let loop_mode = match self.get_i16(GeneratorType::SampleModes, 0) {
+            1 => LoopMode::LoopContinuous,
+            3 => LoopMode::LoopSustain,
+            _ => LoopMode::NoLoop,
+        };
+
+        // Sample header positions are absolute within the sample chunk, while the
+        // loaded sample starts at the header's start
+        let start = header.start as i64;
+        let position = |pos: u32, fine, coarse| {
+            (pos as i64 - start + self.get_address_offset(fine, coarse)).max(0) as usize
+        };
+
+        SampleReaderParams {
+            offset: position(
+                header.start,
+                GeneratorType::StartAddrsOffset,
+                GeneratorType::StartAddrsCoarseOffset,
+            ),
+            end: Some(position(
+                header.end,
+                GeneratorType::EndAddrsOffset,
+                GeneratorType::EndAddrsCoarseOffset,
+            )),
+            loop_mode,
+            loop_start: position(
+                header.loop_start,
+                GeneratorType::StartloopAddrsOffset,
+                GeneratorType::StartloopAddrsCoarseOffset,
+            ),
+            loop_end: position(
+                header.loop_end,
+                GeneratorType::EndloopAddrsOffset,
+                GeneratorType::EndloopAddrsCoarseOffset,
+            ),
+        }
+    }
+
+    /// Converts an envelope time generator (in timecents) into seconds
+    fn get_seconds(&self, ty: GeneratorType) -> f32 {
+        let timecents = self.get_i16(ty, -12000);
+        2.0f32.powf(timecents as f32 / 1200.0)
+    }
+
+    /// The vibrato LFO, with its frequency stored in absolute cents from 8.176 Hz
+    fn vibrato_lfo(&self) -> LfoDescriptor {
+        let frequency = self.get_i16(GeneratorType::FreqVibLFO, 0) as f32;
+        LfoDescriptor {
+            shape: LfoShape::Triangle,
+            frequency: 8.176 * 2.0f32.powf(frequency / 1200.0),
+            delay: self.get_seconds(GeneratorType::DelayVibLFO),
+            fade: 0.0,
+        }
+    }
+
+    fn volume_envelope(&self) -> EnvelopeDescriptor {
+        // Sustain is stored as an attenuation in centibels
+        let sustain = self.get_i16(GeneratorType::SustainVolEnv, 0).clamp(0, 1440);
+
+        EnvelopeDescriptor {
+            start_percent: 0.0,
+            delay: self.get_seconds(GeneratorType::DelayVolEnv),
+            attack: self.get_seconds(GeneratorType::AttackVolEnv),
+            hold: self.get_seconds(GeneratorType::HoldVolEnv),
+            decay: self.get_seconds(GeneratorType::DecayVolEnv),
+            sustain_percent: 10.0f32.powf(-(sustain as f32) / 200.0),
+            release: self.get_seconds(GeneratorType::ReleaseVolEnv),
+            // The volume envelope decays linearly in decibels
+            attack_curve: EnvelopeCurveType::Linear,
+            decay_curve: EnvelopeCurveType::Exponential,
+            release_curve: EnvelopeCurveType::Exponential,
+        }
+    }
+
+    fn volume_envelope_modulation(&self) -> EnvelopeModulation {
+        EnvelopeModulation {
+            key_to_hold: self.get_i16(GeneratorType::KeynumToVolEnvHold, 0) as f32,
+            key_to_decay: self.get_i16(GeneratorType::KeynumToVolEnvDecay, 0) as f32,
+            ..Default::default()
+        }
+    }
+
+    fn modulation_envelope(&self) -> EnvelopeDescriptor {
+        // Sustain is stored as a decrease from the peak, in 0.1% units
+        let sustain = self.get_i16(GeneratorType::SustainModEnv, 0).clamp(0, 1000);
+
+        EnvelopeDescriptor {
+            start_percent: 0.0,
+            delay: self.get_seconds(GeneratorType::DelayModEnv),
+            attack: self.get_seconds(GeneratorType::AttackModEnv),
+            hold: self.get_seconds(GeneratorType::HoldModEnv),
+            decay: self.get_seconds(GeneratorType::DecayModEnv),
+            sustain_percent: 1.0 - sustain as f32 / 1000.0,
+            release: self.get_seconds(GeneratorType::ReleaseModEnv),
+            attack_curve: EnvelopeCurveType::Linear,
+            decay_curve: EnvelopeCurveType::Linear,
+            release_curve: EnvelopeCurveType::Linear,
+        }
+    }
+
+    fn filter(&self) -> Option<FilterDescriptor> {
+        // The cutoff is in absolute cents, and the filter is disabled at its default of 13500
+        let cutoff = self.get_i16(GeneratorType::InitialFilterFc, 13500);
+        if cutoff >= 13500 {
+            return None;
+        }
+
+        // The resonance is stored in centibels
+        let resonance = self.get_i16(GeneratorType::InitialFilterQ, 0).max(0) as f32 / 10.0;
+
+        Some(FilterDescriptor {
+            filter_type: FilterType::LowPass,
+            cutoff: 8.176 * 2.0f32.powf(cutoff as f32 / 1200.0),
+            resonance: FilterDescriptor::q_from_resonance_db(resonance),
+        })
+    }
+}
+
+/// A single instrument zone, resolved through its preset zone, with its sample loaded.
+#[derive(Debug)]
+struct Sf2Region {
+    keys: RangeInclusive<u8>,
+    vels: RangeInclusive<u8>,
+    root_key: u8,
+    scale_tuning: f32, // Cents per key
+    tune: f32,         // Cents
+    attenuation: f32,  // Centibels
+    pan: f32,
+    sample: Arc<[f32]>,
+    sample_params: SampleReaderParams,
+    volume_envelope: ScaledEnvelope,
+    filter: Option<FilterParameters>,
+    pitch_envelope: Option<ModulationEnvelope>,
+    filter_envelope: Option<ModulationEnvelope>,
+    exclusive_class: Option<u32>,
+    vibrato_lfo: LfoParameters,
+    pressure_modulation: PressureModulation,
+    mod_wheel_vibrato: f32, // Cents at full modulation
+    interpolator: Interpolator,
+    velocity_curve: VelocityCurve,
+}
+
+impl Sf2Region {
+    fn contains(&self, key: u8, vel: u8) -> bool {
+        self.keys.contains(&key) && self.vels.contains(&vel)
+    }
+
+    /// The pitch of the sample for the key, relative to the region's root key
+    fn pitch_for_key(&self, key: u8) -> SamplePitch {
+        SamplePitch {
+            key,
+            root_key: self.root_key,
+            scale_tuning: self.scale_tuning,
+            tune: self.tune,
+        }
+    }
+
+    fn gain(&self) -> f32 {
+        10.0f32.powf(-self.attenuation / 200.0)
+    }
+}
+
+/// The region generator values, before any samples are loaded.
+struct Sf2RegionParams {
+    sample_id: usize,
+    keys: RangeInclusive<u8>,
+    vels: RangeInclusive<u8>,
+    root_key: u8,
+    scale_tuning: f32,
+    tune: f32,
+    attenuation: f32,
+    pan: f32,
+    sample_params: SampleReaderParams,
+    volume_envelope: EnvelopeDescriptor,
+    volume_envelope_modulation: EnvelopeModulation,
+    filter: Option<FilterDescriptor>,
+    modulation_envelope: EnvelopeDescriptor,
+    mod_env_to_pitch: f32,  // Cents
+    mod_env_to_filter: f32, // Cents
+    exclusive_class: Option<u32>,
+    vibrato_lfo: LfoDescriptor,
+}
+
+impl Sf2RegionParams {
+    fn parse(gens: &ZoneGenerators, sample_id: usize, header: &SampleHeader) -> Self {
+        let root_key = match gens.get_i16(GeneratorType::OverridingRootKey, -1) {
+            key @ 0..=127 => key as u8,
+            _ if header.origpitch <= 127 => header.origpitch,
+            _ => 60,
+        };
+
+        let coarse_tune = gens.get_i16(GeneratorType::CoarseTune, 0) as f32;
+        let fine_tune = gens.get_i16(GeneratorType::FineTune, 0) as f32;
+        let tune = coarse_tune * 100.0 + fine_tune + header.pitchadj as f32;
+
+        // Zones of the same exclusive class stop each other, such as open and closed hi-hats
+        let exclusive_class = match gens.get_i16(GeneratorType::ExclusiveClass, 0) {
+            0 => None,
+            class => Some(class as u16 as u32),
+        };
+
+        // Pan is stored in 0.1% units, from -500 (left) to 500 (right)
+        let pan = gens.get_i16(GeneratorType::Pan, 0) as f32 / 500.0;
+
+        Sf2RegionParams {
+            sample_id,
+            keys: gens.get_range(GeneratorType::KeyRange),
+            vels: gens.get_range(GeneratorType::VelRange),
+            root_key,
+            scale_tuning: gens.get_i16(GeneratorType::ScaleTuning, 100) as f32,
+            tune,
+            attenuation: gens.get_i16(GeneratorType::InitialAttenuation, 0).max(0) as f32,
+            pan,
+            sample_params: gens.sample_params(header),
+            volume_envelope: gens.volume_envelope(),
+            volume_envelope_modulation: gens.volume_envelope_modulation(),
+            filter: gens.filter(),
+            modulation_envelope: gens.modulation_envelope(),
+            mod_env_to_pitch: gens.get_i16(GeneratorType::ModEnvToPitch, 0) as f32,
+            mod_env_to_filter: gens.get_i16(GeneratorType::ModEnvToFilterFc, 0) as f32,
+            exclusive_class,
+            vibrato_lfo: gens.vibrato_lfo(),
+        }
+    }
+}
+
+/// Reads the 16 bit sample data of a soundfont into floats
+fn read_sample_data(
+    path: &Path,
+    file: &mut File,
+    sf2: &SoundFont2,
+) -> Result<Vec<f32>, LoadSfError> {
+    let smpl = sf2
+        .sample_data
+        .smpl
+        .as_ref()
+        .ok_or_else(|| LoadSfError::invalid(path, "missing sample data chunk"))?;
+
+    // Skip the chunk header
+    file.seek(SeekFrom::Start(smpl.offset() + 8))
+        .map_err(|e| LoadSfError::io(path, e))?;
+
+    let mut bytes = vec![0u8; smpl.len() as usize];
+    file.read_exact(&mut bytes)
+        .map_err(|e| LoadSfError::io(path, e))?;
+
+    Ok(bytes
+        .chunks_exact(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / i16::MAX as f32)
+        .collect())
+}
+
+/// Resolves the zones of a preset down to their instrument zones.
+fn preset_region_params(
+    path: &Path,
+    sf2: &SoundFont2,
+    preset: &Preset,
+) -> Result<Vec<Sf2RegionParams>, LoadSfError> {
+    let mut params = Vec::new();
+    let preset_zones = LayeredZone::from_zones(&preset.zones, |z| z.instrument().is_none());
+    for preset_zone in preset_zones.iter() {
+        let instrument = match preset_zone.local.instrument() {
+            Some(id) => sf2
+                .instruments
+                .get(*id as usize)
+                .ok_or_else(|| LoadSfError::invalid(path, format!("missing instrument {}", id)))?,
+            None => continue,
+        };
+
+        let instrument_zones = LayeredZone::from_zones(&instrument.zones, |z| z.sample().is_none());
+        for instrument_zone in instrument_zones.iter() {
+            let sample_id = match instrument_zone.local.sample() {
+                Some(id) => *id as usize,
+                None => continue,
+            };
+
+            let gens = ZoneGenerators {
+                preset: preset_zone,
+                instrument: instrument_zone,
+            };
+            let header = sf2.sample_headers.get(sample_id).ok_or_else(|| {
+                LoadSfError::invalid(path, format!("missing sample {}", sample_id))
+            })?;
+            params.push(Sf2RegionParams::parse(&gens, sample_id, header));
+        }
+    }
+
+    Ok(params)
+}
+
+/// A soundfont built from the presets of an SF2 file.
+///
+/// Each preset's zones are resolved down to their instrument zones, and each
+/// of those is mapped onto a sampled voice spawner for every key and velocity
+/// within its range.
+#[derive(Debug)]
+pub struct Sf2Soundfont {
+    /// The regions of each preset, by bank and program
+    presets: HashMap<(u16, u16), Vec<Sf2Region>>,
+    /// The preset played for every bank and program, if only one was loaded
+    fixed_preset: Option<(u16, u16)>,
+    stream_params: AudioStreamParams,
+}
+
+impl Sf2Soundfont {
+    /// Loads a single preset of an SF2 file, which is played regardless of the
+    /// bank and program selected on the channel.
+    pub fn new(
+        sf2_path: &Path,
+        bank: u16,
+        preset: u16,
+        sample_rate: u32,
+        channels: u16,
+    ) -> Result<Self, LoadSfError> {
+        Self::load(sf2_path, Some((bank, preset)), sample_rate, channels)
+    }
+
+    /// Loads every preset of an SF2 file, to be selected through bank select
+    /// and program change events.
+    pub fn new_all_presets(
+        sf2_path: &Path,
+        sample_rate: u32,
+        channels: u16,
+    ) -> Result<Self, LoadSfError> {
+        Self::load(sf2_path, None, sample_rate, channels)
+    }
+
+    fn load(
+        sf2_path: &Path,
+        fixed_preset: Option<(u16, u16)>,
+        sample_rate: u32,
+        channels: u16,
+    ) -> Result<Self, LoadSfError> {
+        let mut file = File::open(sf2_path).map_err(|e| LoadSfError::io(sf2_path, e))?;
+        let data = SFData::load(&mut file)
+            .map_err(|e| LoadSfError::invalid(sf2_path, format!("{:?}", e)))?;
+        let sf2 = SoundFont2::from_data(data);
+
+        let presets: Vec<&Preset> = match fixed_preset {
+            Some((bank, preset)) => {
+                let preset = sf2
+                    .presets
+                    .iter()
+                    .find(|p| p.header.bank == bank && p.header.preset == preset)
+                    .ok_or_else(|| LoadSfError::PresetNotFound {
+                        path: sf2_path.into(),
+                        bank,
+                        preset,
+                    })?;
+                vec![preset]
+            }
+            None => sf2.presets.iter().collect(),
+        };
+
+        let mut params = Vec::new();
+        for preset in presets {
+            let id = (preset.header.bank, preset.header.preset);
+            for region in preset_region_params(sf2_path, &sf2, preset)? {
+                params.push((id, region));
+            }
+        }
+
+        let sample_data = read_sample_data(sf2_path, &mut file, &sf2)?;
+
+        // Zones often share samples, so each sample is only loaded once
+        let mut sample_ids: Vec<usize> = params.iter().map(|(_, p)| p.sample_id).collect();
+        sample_ids.sort_unstable();
+        sample_ids.dedup();
+
+        let samples: HashMap<usize, Arc<[f32]>> = sample_ids
+            .par_iter()
+            .map(|&id| {
+                let header = &sf2.sample_headers[id];
+                let start = (header.start as usize).min(sample_data.len());
+                let end = (header.end as usize).max(start).min(sample_data.len());
+                let data = &sample_data[start..end];
+
+                let samples: Arc<[f32]> = if header.sample_rate == sample_rate {
+                    data.into()
+                } else {
+                    let resampler = SincResampler::new(10000, header.sample_rate, 32);
+                    resampler.resample_vec(data, sample_rate).into()
+                };
+
+                (id, samples)
+            })
+            .collect();
+
+        let mut presets: HashMap<(u16, u16), Vec<Sf2Region>> = HashMap::new();
+        for (id, params) in params {
+            // The modulation envelope is shared between the pitch and the filter
+            let modulation_envelope_params =
+                Arc::new(params.modulation_envelope.to_envelope_params(sample_rate));
+
+            let region = Sf2Region {
+                sample: samples[&params.sample_id].clone(),
+                sample_params: params.sample_params.resampled(
+                    sf2.sample_headers[params.sample_id].sample_rate,
+                    sample_rate,
+                ),
+                keys: params.keys,
+                vels: params.vels,
+                root_key: params.root_key,
+                scale_tuning: params.scale_tuning,
+                tune: params.tune,
+                attenuation: params.attenuation,
+                pan: params.pan,
+                volume_envelope: ScaledEnvelope::new(
+                    params.volume_envelope,
+                    params.volume_envelope_modulation,
+                    sample_rate,
+                ),
+                filter: params
+                    .filter
+                    .map(|filter| filter.to_filter_params(sample_rate)),
+                pitch_envelope: ModulationEnvelope::new(
+                    modulation_envelope_params.clone(),
+                    params.mod_env_to_pitch,
+                ),
+                filter_envelope: ModulationEnvelope::new(
+                    modulation_envelope_params,
+                    params.mod_env_to_filter,
+                ),
+                exclusive_class: params.exclusive_class,
+                vibrato_lfo: params.vibrato_lfo.to_lfo_params(sample_rate),
+                pressure_modulation: PressureModulation::default(),
+                mod_wheel_vibrato: 50.0,
+                interpolator: Interpolator::default(),
+                velocity_curve: VelocityCurve::Concave,
+            };
+            presets.entry(id).or_default().push(region);
+        }
+
+        Ok(Self {
+            presets,
+            fixed_preset,
+            stream_params: AudioStreamParams::new(sample_rate, channels),
+        })
+    }
+
+    fn regions_mut(&mut self) -> impl Iterator<Item = &mut Sf2Region> {
+        self.presets.values_mut().flatten()
+    }
+
+    /// Sets how the samples of every zone are interpolated
+    pub fn with_interpolator(mut self, interpolator: Interpolator) -> Self {
+        for region in self.regions_mut() {
+            region.interpolator = interpolator;
+        }
+        self
+    }
+
+    /// Sets the curve mapping the velocity to the amplitude of every zone
+    pub fn with_velocity_curve(mut self, velocity_curve: VelocityCurve) -> Self {
+        for region in self.regions_mut() {
+            region.velocity_curve = velocity_curve.clone();
+        }
+        self
+    }
+
+    /// Sets how the key pressure modulates the voices of every zone. The vibrato
+    /// follows the vibrato LFO of each zone.
+    pub fn with_pressure_modulation(mut self, pressure_modulation: PressureModulation) -> Self {
+        for region in self.regions_mut() {
+            region.pressure_modulation = pressure_modulation;
+        }
+        self
+    }
+
+    /// Sets the depth of the vibrato at full mod wheel (CC1) in cents, for every
+    /// zone. It defaults to the 50 cents of the SF2 default modulator, and
+    /// follows the vibrato LFO of each zone.
+    pub fn with_mod_wheel_vibrato(mut self, depth: f32) -> Self {
+        for region in self.regions_mut() {
+            region.mod_wheel_vibrato = depth;
+        }
+        self
+    }
+
+    /// The regions of the preset played for a bank and program
+    fn preset_regions(&self, bank: u8, preset: u8) -> &[Sf2Region] {
+        let id = self.fixed_preset.unwrap_or((bank as u16, preset as u16));
+        self.presets.get(&id).map(Vec::as_slice).unwrap_or(&[])
+    }
+}
+
+impl SoundfontBase for Sf2Soundfont {
+    fn stream_params(&self) -> &AudioStreamParams {
+        &self.stream_params
+    }
+
+    fn has_preset(&self, bank: u8, preset: u8) -> bool {
+        !self.preset_regions(bank, preset).is_empty()
+    }
+
+    fn get_attack_voice_spawners_at(
+        &self,
+        bank: u8,
+        preset: u8,
+        key: u8,
+        vel: u8,
+    ) -> Vec<Box<dyn VoiceSpawner>> {
+        use simdeez::*; // nuts
+
+        use simdeez::avx2::*;
+        use simdeez::scalar::*;
+        use simdeez::sse2::*;
+        use simdeez::sse41::*;
+
+        simd_runtime_generate!(
+            fn get(key: u8, vel: u8, regions: &[&Sf2Region]) -> Vec<Box<dyn VoiceSpawner>> {
+                regions
+                    .iter()
+                    .map(|region| {
+                        let spawner = SampledVoiceSpawner::<S>::new(
+                            vel,
+                            region.pitch_for_key(key),
+                            region.gain(),
+                            region.pan,
+                            region.volume_envelope.params_at(key, vel),
+                            vec![region.sample.clone()],
+                            region.sample_params.clone(),
+                        )
+                        .with_interpolator(region.interpolator)
+                        .with_velocity_curve(&region.velocity_curve)
+                        .with_filter(region.filter)
+                        .with_modulation_envelopes(
+                            region.pitch_envelope.clone(),
+                            region.filter_envelope.clone(),
+                        )
+                        .with_choke_groups(region.exclusive_class, region.exclusive_class)
+                        .with_pressure_modulation(region.pressure_modulation, region.vibrato_lfo)
+                        .with_mod_wheel_vibrato(region.mod_wheel_vibrato, region.vibrato_lfo);
+                        Box::new(spawner) as Box<dyn VoiceSpawner>
+                    })
+                    .collect()
+            }
+        );
+
+        let regions: Vec<&Sf2Region> = self
+            .preset_regions(bank, preset)
+            .iter()
+            .filter(|region| region.contains(key, vel))
+            .collect();
+
+        get_runtime_select(key, vel, &regions)
+    }
+
+    fn get_release_voice_spawners_at(
+        &self,
+        _bank: u8,
+        _preset: u8,
+        _key: u8,
+        _vel: u8,
+    ) -> Vec<Box<dyn VoiceSpawner>> {
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_combine_generator() {
+        // Preset zones offset the instrument's value, or the default if it has none
+        let scale_tuning = GeneratorType::ScaleTuning;
+        assert_eq!(
+            combine_generator(scale_tuning, Some(100), Some(-50), 100),
+            50
+        );
+        assert_eq!(combine_generator(scale_tuning, None, Some(-100), 100), 0);
+        assert_eq!(combine_generator(scale_tuning, Some(50), None, 100), 50);
+        assert_eq!(
+            combine_generator(GeneratorType::VibLfoToPitch, Some(20), Some(30), 0),
+            50
+        );
+        assert_eq!(
+            combine_generator(GeneratorType::ReverbEffectsSend, None, Some(200), 0),
+            200
+        );
+
+        // Instrument level generators ignore the preset zone
+        let root_key = GeneratorType::OverridingRootKey;
+        assert_eq!(combine_generator(root_key, Some(60), Some(12), -1), 60);
+        assert_eq!(
+            combine_generator(GeneratorType::SampleModes, None, Some(1), 0),
+            0
+        );
+    }
+}
@@ -0,0 +1,501 @@
+use std::{
+    collections::HashMap,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use sofiza::{fil_type, loop_mode, trigger, Instrument, Opcode, Region};
+
+use super::{
+    audio::{AudioFileLoader, LoadedAudio},
+    Interpolator, LoadSfError, ModulationEnvelope, PressureModulation, SamplePitch,
+    SampledVoiceSpawner, ScaledEnvelope, SoundfontBase, VoiceSpawner,
+};
+use crate::{
+    voice::{
+        EnvelopeCurveType, EnvelopeDescriptor, EnvelopeModulation, FilterDescriptor,
+        FilterParameters, FilterType, LfoDescriptor, LfoParameters, LfoShape, LoopMode,
+        SampleReaderParams, VelocityCurve,
+    },
+    AudioStreamParams,
+};
+
+/// Reads an opcode's value from a region, falling back to the region's group
+/// and then the global header.
+macro_rules! opcode {
+    ($sfz:expr, $region:expr, $name:ident) => {
+        match lookup_opcode($sfz, $region, stringify!($name)) {
+            Some(Opcode::$name(value)) => Some(value.clone()),
+            _ => None,
+        }
+    };
+}
+
+fn lookup_opcode<'a>(sfz: &'a Instrument, region: &'a Region, name: &str) -> Option<&'a Opcode> {
+    region
+        .get(name)
+        .or_else(|| {
+            region
+                .group
+                .and_then(|group| sfz.groups.get(group))
+                .and_then(|group| group.get(name))
+        })
+        .or_else(|| sfz.global.get(name))
+}
+
+/// Collects the `amp_velcurve_N` points of a region, with the region's points
+/// overriding its group's, and the group's overriding the global header's.
+fn velocity_curve_points(sfz: &Instrument, region: &Region) -> Vec<(u8, f32)> {
+    let mut points = HashMap::new();
+    let mut add_points = |opcodes: &HashMap<String, Opcode>| {
+        for opcode in opcodes.values() {
+            if let Opcode::amp_velcurve_N((vel, gain)) = opcode {
+                points.insert(*vel, *gain);
+            }
+        }
+    };
+
+    add_points(&sfz.global);
+    if let Some(group) = region.group.and_then(|group| sfz.groups.get(group)) {
+        add_points(group);
+    }
+    add_points(region);
+
+    points.into_iter().collect()
+}
+
+/// A single parsed SFZ region, with its samples loaded.
+#[derive(Debug)]
+struct SfzRegion {
+    keys: RangeInclusive<u8>,
+    vels: RangeInclusive<u8>,
+    pitch_keycenter: u8,
+    tune: f32,   // Cents
+    volume: f32, // Decibels
+    pan: f32,    // -1 (left) to 1 (right)
+    release_trigger: bool,
+    rt_decay: f32, // Decibels per second
+    samples: Vec<Arc<[f32]>>,
+    sample_params: SampleReaderParams,
+    volume_envelope: ScaledEnvelope,
+    filter: Option<FilterParameters>,
+    vibrato_lfo: LfoParameters,
+    pressure_modulation: PressureModulation,
+    mod_wheel_vibrato: f32, // Cents at full modulation
+    pitch_envelope: Option<ModulationEnvelope>,
+    filter_envelope: Option<ModulationEnvelope>,
+    group: Option<u32>,  // Choke group
+    off_by: Option<u32>, // Choke group that stops the region's voices
+    velocity_curve: VelocityCurve,
+    interpolator: Interpolator,
+}
+
+impl SfzRegion {
+    fn contains(&self, key: u8, vel: u8) -> bool {
+        self.keys.contains(&key) && self.vels.contains(&vel)
+    }
+
+    /// The pitch of the samples for the key, relative to the region's key center
+    fn pitch_for_key(&self, key: u8) -> SamplePitch {
+        SamplePitch {
+            tune: self.tune,
+            ..SamplePitch::new(key, self.pitch_keycenter)
+        }
+    }
+
+    fn gain(&self) -> f32 {
+        10.0f32.powf(self.volume / 20.0)
+    }
+}
+
+/// The region opcodes relevant to the voice spawners, before any samples are loaded.
+struct SfzRegionParams {
+    sample_path: PathBuf,
+    keys: RangeInclusive<u8>,
+    vels: RangeInclusive<u8>,
+    pitch_keycenter: u8,
+    tune: f32,
+    volume: f32,
+    pan: f32,
+    release_trigger: bool,
+    rt_decay: f32,
+    sample_params: SampleReaderParams,
+    volume_envelope: EnvelopeDescriptor,
+    volume_envelope_modulation: EnvelopeModulation,
+    filter: Option<FilterDescriptor>,
+    vibrato_lfo: LfoDescriptor,
+    pitch_envelope: EnvelopeDescriptor,
+    pitch_envelope_depth: f32, // Cents
+    filter_envelope: EnvelopeDescriptor,
+    filter_envelope_depth: f32, // Cents
+    group: Option<u32>,
+    off_by: Option<u32>,
+    velocity_curve: VelocityCurve,
+}
+
+impl SfzRegionParams {
+    fn parse(sfz: &Instrument, region: &Region) -> Option<Self> {
+        let sample = opcode!(sfz, region, sample)?;
+        let sample_path = sfz.default_path.join(sample);
+
+        // `key` is a shorthand for setting lokey, hikey and pitch_keycenter at once
+        let key = opcode!(sfz, region, key);
+        let lokey = opcode!(sfz, region, lokey).or(key).unwrap_or(0);
+        let hikey = opcode!(sfz, region, hikey).or(key).unwrap_or(127);
+        let pitch_keycenter = opcode!(sfz, region, pitch_keycenter).or(key).unwrap_or(60);
+
+        let lovel = opcode!(sfz, region, lovel).unwrap_or(1);
+        let hivel = opcode!(sfz, region, hivel).unwrap_or(127);
+
+        let transpose = opcode!(sfz, region, transpose).unwrap_or(0) as f32;
+        let tune = opcode!(sfz, region, tune).unwrap_or(0) as f32 + transpose * 100.0;
+        let volume = opcode!(sfz, region, volume).unwrap_or(0.0);
+        let pan = opcode!(sfz, region, pan).unwrap_or(0.0) / 100.0;
+
+        let release_trigger = opcode!(sfz, region, trigger) == Some(trigger::release);
+        let rt_decay = opcode!(sfz, region, rt_decay).unwrap_or(0.0);
+
+        // Sample positions in SFZ are inclusive, while the sample reader's ends are exclusive
+        let loop_start = opcode!(sfz, region, loop_start);
+        let loop_end = opcode!(sfz, region, loop_end);
+        let loop_mode = match opcode!(sfz, region, loop_mode) {
+            Some(loop_mode::no_loop) => LoopMode::NoLoop,
+            Some(loop_mode::one_shot) => LoopMode::OneShot,
+            Some(loop_mode::loop_continuous) => LoopMode::LoopContinuous,
+            Some(loop_mode::loop_sustain) => LoopMode::LoopSustain,
+            None if loop_end.is_some() => LoopMode::LoopContinuous,
+            None => LoopMode::NoLoop,
+        };
+
+        // Release voices don't get a note off of their own, so they always play to the end
+        let loop_mode = if release_trigger {
+            LoopMode::OneShot
+        } else {
+            loop_mode
+        };
+        let sample_params = SampleReaderParams {
+            offset: opcode!(sfz, region, offset).unwrap_or(0) as usize,
+            end: opcode!(sfz, region, end).map(|end| (end as i64 + 1).max(0) as usize),
+            loop_mode,
+            loop_start: loop_start.unwrap_or(0) as usize,
+            loop_end: loop_end.map(|end| end as usize + 1).unwrap_or(0),
+        };
+
+        let volume_envelope = EnvelopeDescriptor {
+            start_percent: opcode!(sfz, region, ampeg_start).unwrap_or(0.0) / 100.0,
+            delay: opcode!(sfz, region, ampeg_delay).unwrap_or(0.0),
+            attack: opcode!(sfz, region, ampeg_attack).unwrap_or(0.0),
+            hold: opcode!(sfz, region, ampeg_hold).unwrap_or(0.0),
+            decay: opcode!(sfz, region, ampeg_decay).unwrap_or(0.0),
+            sustain_percent: opcode!(sfz, region, ampeg_sustain).unwrap_or(100.0) / 100.0,
+            release: opcode!(sfz, region, ampeg_release).unwrap_or(0.001),
+            attack_curve: EnvelopeCurveType::Linear,
+            decay_curve: EnvelopeCurveType::Linear,
+            release_curve: EnvelopeCurveType::Linear,
+        };
+
+        let volume_envelope_modulation = EnvelopeModulation {
+            vel_to_delay: opcode!(sfz, region, ampeg_vel2delay).unwrap_or(0.0),
+            vel_to_attack: opcode!(sfz, region, ampeg_vel2attack).unwrap_or(0.0),
+            vel_to_hold: opcode!(sfz, region, ampeg_vel2hold).unwrap_or(0.0),
+            vel_to_decay: opcode!(sfz, region, ampeg_vel2decay).unwrap_or(0.0),
+            vel_to_release: opcode!(sfz, region, ampeg_vel2release).unwrap_or(0.0),
+            ..Default::default()
+        };
+
+        // The mod wheel and pressure vibratos use the pitch LFO's timing, even if the
+        // LFO has no depth
+        let vibrato_lfo = LfoDescriptor {
+            shape: LfoShape::Sine,
+            frequency: opcode!(sfz, region, pitchlfo_freq).unwrap_or(5.0),
+            delay: opcode!(sfz, region, pitchlfo_delay).unwrap_or(0.0),
+            fade: opcode!(sfz, region, pitchlfo_fade).unwrap_or(0.0),
+        };
+
+        let pitch_envelope = EnvelopeDescriptor {
+            start_percent: opcode!(sfz, region, pitcheg_start).unwrap_or(0.0) / 100.0,
+            delay: opcode!(sfz, region, pitcheg_delay).unwrap_or(0.0),
+            attack: opcode!(sfz, region, pitcheg_attack).unwrap_or(0.0),
+            hold: opcode!(sfz, region, pitcheg_hold).unwrap_or(0.0),
+            decay: opcode!(sfz, region, pitcheg_decay).unwrap_or(0.0),
+            sustain_percent: opcode!(sfz, region, pitcheg_sustain).unwrap_or(100.0) / 100.0,
+            release: opcode!(sfz, region, pitcheg_release).unwrap_or(0.001),
+            attack_curve: EnvelopeCurveType::Linear,
+            decay_curve: EnvelopeCurveType::Linear,
+            release_curve: EnvelopeCurveType::Linear,
+        };
+        let pitch_envelope_depth = opcode!(sfz, region, pitcheg_depth).unwrap_or(0) as f32;
+
+        let filter_envelope = EnvelopeDescriptor {
+            start_percent: opcode!(sfz, region, fileg_start).unwrap_or(0.0) / 100.0,
+            delay: opcode!(sfz, region, fileg_delay).unwrap_or(0.0),
+            attack: opcode!(sfz, region, fileg_attack).unwrap_or(0.0),
+            hold: opcode!(sfz, region, fileg_hold).unwrap_or(0.0),
+            decay: opcode!(sfz, region, fileg_decay).unwrap_or(0.0),
+            sustain_percent: opcode!(sfz, region, fileg_sustain).unwrap_or(100.0) / 100.0,
+            release: opcode!(sfz, region, fileg_release).unwrap_or(0.001),
+            attack_curve: EnvelopeCurveType::Linear,
+            decay_curve: EnvelopeCurveType::Linear,
+            release_curve: EnvelopeCurveType::Linear,
+        };
+        let filter_envelope_depth = opcode!(sfz, region, fileg_depth).unwrap_or(0) as f32;
+
+        // Group 0 is the default group, which doesn't choke anything
+        let group = opcode!(sfz, region, group).filter(|group| *group != 0);
+        let off_by = opcode!(sfz, region, off_by).filter(|group| *group != 0);
+
+        let velocity_curve_points = velocity_curve_points(sfz, region);
+        let velocity_curve = if velocity_curve_points.is_empty() {
+            VelocityCurve::Concave
+        } else {
+            VelocityCurve::from_points(&velocity_curve_points)
+        };
+
+        // The filter is only enabled when a cutoff is set. One pole filters are
+        // approximated by their two pole counterparts.
+        let filter = opcode!(sfz, region, cutoff).map(|cutoff| {
+            let filter_type = match opcode!(sfz, region, fil_type) {
+                Some(fil_type::hpf_1p) | Some(fil_type::hpf_2p) => FilterType::HighPass,
+                Some(fil_type::bpf_2p) => FilterType::BandPass,
+                Some(fil_type::brf_2p) => FilterType::Notch,
+                _ => FilterType::LowPass,
+            };
+            let resonance = opcode!(sfz, region, resonance).unwrap_or(0.0);
+
+            FilterDescriptor {
+                filter_type,
+                cutoff,
+                resonance: FilterDescriptor::q_from_resonance_db(resonance),
+            }
+        });
+
+        Some(SfzRegionParams {
+            sample_path,
+            keys: lokey.min(127)..=hikey.min(127),
+            vels: lovel.min(127)..=hivel.min(127),
+            pitch_keycenter: pitch_keycenter.min(127),
+            tune,
+            volume,
+            pan,
+            release_trigger,
+            rt_decay,
+            sample_params,
+            volume_envelope,
+            volume_envelope_modulation,
+            filter,
+            vibrato_lfo,
+            pitch_envelope,
+            pitch_envelope_depth,
+            filter_envelope,
+            filter_envelope_depth,
+            group,
+            off_by,
+            velocity_curve,
+        })
+    }
+}
+
+/// A soundfont built from an SFZ instrument file.
+///
+/// Each SFZ region is mapped onto a sampled voice spawner for every key and
+/// velocity within its range.
+#[derive(Debug)]
+pub struct SfzSoundfont {
+    regions: Vec<SfzRegion>,
+    stream_params: AudioStreamParams,
+}
+
+impl SfzSoundfont {
+    pub fn new(sfz_path: &Path, sample_rate: u32, channels: u16) -> Result<Self, LoadSfError> {
+        let sfz = Instrument::from_file(sfz_path)
+            .map_err(|e| LoadSfError::invalid(sfz_path, format!("{:?}", e)))?;
+
+        let params: Vec<SfzRegionParams> = sfz
+            .regions
+            .iter()
+            .filter_map(|region| SfzRegionParams::parse(&sfz, region))
+            .collect();
+
+        // Many regions often share the same sample file, so each file is only loaded once
+        let mut sample_paths: Vec<PathBuf> = params.iter().map(|p| p.sample_path.clone()).collect();
+        sample_paths.sort();
+        sample_paths.dedup();
+
+        let samples: HashMap<PathBuf, LoadedAudio> = sample_paths
+            .par_iter()
+            .map(|path| {
+                let samples = AudioFileLoader::load_wav(path, sample_rate)?;
+                Ok((path.clone(), samples))
+            })
+            .collect::<Result<_, LoadSfError>>()?;
+
+        let regions = params
+            .into_iter()
+            .map(|params| {
+                let (samples, file_rate) = &samples[&params.sample_path];
+                SfzRegion {
+                    samples: samples.clone(),
+                    sample_params: params.sample_params.resampled(*file_rate, sample_rate),
+                    keys: params.keys,
+                    vels: params.vels,
+                    pitch_keycenter: params.pitch_keycenter,
+                    tune: params.tune,
+                    volume: params.volume,
+                    pan: params.pan,
+                    release_trigger: params.release_trigger,
+                    rt_decay: params.rt_decay,
+                    volume_envelope: ScaledEnvelope::new(
+                        params.volume_envelope,
+                        params.volume_envelope_modulation,
+                        sample_rate,
+                    ),
+                    filter: params
+                        .filter
+                        .map(|filter| filter.to_filter_params(sample_rate)),
+                    vibrato_lfo: params.vibrato_lfo.to_lfo_params(sample_rate),
+                    pressure_modulation: PressureModulation::default(),
+                    // The SFZ parser has no CC opcodes, so follow the SF2 default
+                    mod_wheel_vibrato: 50.0,
+                    pitch_envelope: ModulationEnvelope::new(
+                        Arc::new(params.pitch_envelope.to_envelope_params(sample_rate)),
+                        params.pitch_envelope_depth,
+                    ),
+                    filter_envelope: ModulationEnvelope::new(
+                        Arc::new(params.filter_envelope.to_envelope_params(sample_rate)),
+                        params.filter_envelope_depth,
+                    ),
+                    group: params.group,
+                    off_by: params.off_by,
+                    velocity_curve: params.velocity_curve,
+                    interpolator: Interpolator::default(),
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            regions,
+            stream_params: AudioStreamParams::new(sample_rate, channels),
+        })
+    }
+
+    /// Sets how the samples of every region are interpolated
+    pub fn with_interpolator(mut self, interpolator: Interpolator) -> Self {
+        for region in self.regions.iter_mut() {
+            region.interpolator = interpolator;
+        }
+        self
+    }
+
+    /// Sets the velocity curve of every region, replacing the curves defined
+    /// by the `amp_velcurve_N` opcodes.
+    pub fn with_velocity_curve(mut self, velocity_curve: VelocityCurve) -> Self {
+        for region in self.regions.iter_mut() {
+            region.velocity_curve = velocity_curve.clone();
+        }
+        self
+    }
+
+    /// Sets how the key pressure modulates the voices of every region. The
+    /// vibrato follows the `pitchlfo_freq`, `pitchlfo_delay` and `pitchlfo_fade`
+    /// opcodes of each region, with a 5 Hz default frequency.
+    pub fn with_pressure_modulation(mut self, pressure_modulation: PressureModulation) -> Self {
+        for region in self.regions.iter_mut() {
+            region.pressure_modulation = pressure_modulation;
+        }
+        self
+    }
+
+    /// Sets the depth of the vibrato at full mod wheel (CC1) in cents, for every
+    /// region. It defaults to the 50 cents that SF2 soundfonts use, and the
+    /// vibrato uses the same LFO as the pressure vibrato.
+    pub fn with_mod_wheel_vibrato(mut self, depth: f32) -> Self {
+        for region in self.regions.iter_mut() {
+            region.mod_wheel_vibrato = depth;
+        }
+        self
+    }
+}
+
+impl SfzSoundfont {
+    fn get_voice_spawners_at(
+        &self,
+        key: u8,
+        vel: u8,
+        release_trigger: bool,
+    ) -> Vec<Box<dyn VoiceSpawner>> {
+        use simdeez::*; // nuts
+
+        use simdeez::avx2::*;
+        use simdeez::scalar::*;
+        use simdeez::sse2::*;
+        use simdeez::sse41::*;
+
+        simd_runtime_generate!(
+            fn get(key: u8, vel: u8, regions: &[&SfzRegion]) -> Vec<Box<dyn VoiceSpawner>> {
+                regions
+                    .iter()
+                    .map(|region| {
+                        let spawner = SampledVoiceSpawner::<S>::new(
+                            vel,
+                            region.pitch_for_key(key),
+                            region.gain(),
+                            region.pan,
+                            region.volume_envelope.params_at(key, vel),
+                            region.samples.clone(),
+                            region.sample_params.clone(),
+                        )
+                        .with_release_decay(region.rt_decay)
+                        .with_velocity_curve(&region.velocity_curve)
+                        .with_interpolator(region.interpolator)
+                        .with_filter(region.filter)
+                        .with_modulation_envelopes(
+                            region.pitch_envelope.clone(),
+                            region.filter_envelope.clone(),
+                        )
+                        .with_choke_groups(region.group, region.off_by)
+                        .with_pressure_modulation(region.pressure_modulation, region.vibrato_lfo)
+                        .with_mod_wheel_vibrato(region.mod_wheel_vibrato, region.vibrato_lfo);
+                        Box::new(spawner) as Box<dyn VoiceSpawner>
+                    })
+                    .collect()
+            }
+        );
+
+        let regions: Vec<&SfzRegion> = self
+            .regions
+            .iter()
+            .filter(|region| region.release_trigger == release_trigger)
+            .filter(|region| region.contains(key, vel))
+            .collect();
+
+        get_runtime_select(key, vel, &regions)
+    }
+}
+
+impl SoundfontBase for SfzSoundfont {
+    fn stream_params<'a>(&'a self) -> &'a AudioStreamParams {
+        &self.stream_params
+    }
+
+    fn get_attack_voice_spawners_at(
+        &self,
+        _bank: u8,
+        _preset: u8,
+        key: u8,
+        vel: u8,
+    ) -> Vec<Box<dyn VoiceSpawner>> {
+        self.get_voice_spawners_at(key, vel, false)
+    }
+
+    fn get_release_voice_spawners_at(
+        &self,
+        _bank: u8,
+        _preset: u8,
+        key: u8,
+        vel: u8,
+    ) -> Vec<Box<dyn VoiceSpawner>> {
+        self.get_voice_spawners_at(key, vel, true)
+    }
+}
@@ -0,0 +1,197 @@
+use std::{error::Error, fmt, fs, io, path::Path, path::PathBuf};
+
+use crate::helpers::FREQS;
+
+mod mts;
+mod scala;
+use scala::{KeyboardMapping, Scale};
+
+/// An error encountered while loading a tuning.
+#[derive(Debug)]
+pub enum TuningError {
+    /// A file couldn't be opened or read
+    Io { path: PathBuf, error: io::Error },
+
+    /// A Scala scale (.scl) couldn't be parsed
+    InvalidScale(String),
+
+    /// A Scala keyboard mapping (.kbm) couldn't be parsed, or doesn't fit its scale
+    InvalidMapping(String),
+}
+
+impl fmt::Display for TuningError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TuningError::Io { path, error } => {
+                write!(f, "failed to read {}: {}", path.display(), error)
+            }
+            TuningError::InvalidScale(reason) => write!(f, "invalid scale: {}", reason),
+            TuningError::InvalidMapping(reason) => {
+                write!(f, "invalid keyboard mapping: {}", reason)
+            }
+        }
+    }
+}
+
+impl Error for TuningError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TuningError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// The frequency of each MIDI key.
+///
+/// Voice spawners play each key at its frequency relative to the sample's root
+/// key, so samples are assumed to be recorded at their root key's pitch in
+/// 12 tone equal temperament.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tuning {
+    frequencies: [f32; 128], // Hz
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Tuning::equal_temperament()
+    }
+}
+
+impl Tuning {
+    /// 12 tone equal temperament, with A4 (key 69) at 440Hz
+    pub fn equal_temperament() -> Self {
+        Tuning {
+            frequencies: *FREQS,
+        }
+    }
+
+    pub fn from_frequencies(frequencies: [f32; 128]) -> Self {
+        Tuning { frequencies }
+    }
+
+    /// Parses a Scala scale and an optional keyboard mapping. Without a mapping,
+    /// the scale starts on key 60 and key 69 is tuned to 440Hz.
+    pub fn from_scala(scale: &str, mapping: Option<&str>) -> Result<Self, TuningError> {
+        let scale = Scale::parse(scale).map_err(TuningError::InvalidScale)?;
+        let mapping = match mapping {
+            Some(mapping) => {
+                KeyboardMapping::parse(mapping).map_err(TuningError::InvalidMapping)?
+            }
+            None => KeyboardMapping::linear(scale.len()),
+        };
+
+        let mut frequencies = *FREQS;
+        mapping
+            .apply(&scale, &mut frequencies)
+            .map_err(TuningError::InvalidMapping)?;
+
+        Ok(Tuning { frequencies })
+    }
+
+    /// Loads a Scala scale (.scl) file and an optional keyboard mapping (.kbm) file
+    pub fn from_scala_files(scale: &Path, mapping: Option<&Path>) -> Result<Self, TuningError> {
+        let read = |path: &Path| {
+            fs::read_to_string(path).map_err(|error| TuningError::Io {
+                path: path.into(),
+                error,
+            })
+        };
+
+        let scale = read(scale)?;
+        let mapping = match mapping {
+            Some(mapping) => Some(read(mapping)?),
+            None => None,
+        };
+
+        Tuning::from_scala(&scale, mapping.as_deref())
+    }
+
+    /// The frequency of a key, in Hz
+    pub fn frequency(&self, key: u8) -> f32 {
+        self.frequencies[key.min(127) as usize]
+    }
+
+    pub fn set_frequency(&mut self, key: u8, frequency: f32) {
+        self.frequencies[key.min(127) as usize] = frequency;
+    }
+
+    /// Applies a MIDI Tuning Standard single note tuning change or bulk tuning
+    /// dump SysEx message. Returns false if the message isn't either of those.
+    pub fn apply_mts_sysex(&mut self, message: &[u8]) -> bool {
+        mts::apply(message, &mut self.frequencies)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() / b < 0.0001, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_scala_tuning() {
+        // 12 tone equal temperament matches the default tuning
+        let scale = "! 12-tet.scl\n!\n12 tone equal temperament\n 12\n!\n\
+            100.0\n200.\n300.0\n400.0\n500.0\n600.0\n700.0\n800.0\n900.0\n1000.0\n1100.0\n2/1\n";
+        let tuning = Tuning::from_scala(scale, None).unwrap();
+        for key in 0..128 {
+            assert_close(tuning.frequency(key), FREQS[key as usize]);
+        }
+
+        // Just intonation fifths, mapped with C4 at 261.6256Hz
+        let scale = "Fifths\n2\n3/2 perfect fifth\n2\n";
+        let mapping = "! fifths.kbm\n0\n0\n127\n60\n60\n261.6256\n2\n";
+        let tuning = Tuning::from_scala(scale, Some(mapping)).unwrap();
+        assert_close(tuning.frequency(60), 261.6256);
+        assert_close(tuning.frequency(61), 261.6256 * 1.5);
+        assert_close(tuning.frequency(62), 261.6256 * 2.0);
+        assert_close(tuning.frequency(59), 261.6256 * 0.75);
+
+        // Unmapped keys keep their equal temperament frequency
+        let mapping = "3\n0\n127\n60\n60\n261.6256\n2\n0\nx\n1\n";
+        let tuning = Tuning::from_scala(scale, Some(mapping)).unwrap();
+        assert_close(tuning.frequency(61), FREQS[61]);
+        assert_close(tuning.frequency(62), 261.6256 * 1.5);
+        assert_close(tuning.frequency(63), 261.6256 * 2.0);
+
+        assert!(Tuning::from_scala("Empty\n", None).is_err());
+        assert!(Tuning::from_scala("Bad\n1\nfoo\n", None).is_err());
+    }
+
+    #[test]
+    fn test_mts_sysex() {
+        let mut tuning = Tuning::default();
+
+        // Key 60 a quarter tone up, key 61 unchanged, key 62 at A4
+        let message = [
+            0xF0, 0x7F, 0x7F, 0x08, 0x02, 0x00, 0x03, 60, 60, 0x40, 0x00, 61, 0x7F, 0x7F, 0x7F, 62,
+            69, 0x00, 0x00, 0xF7,
+        ];
+        assert!(tuning.apply_mts_sysex(&message));
+        assert_close(tuning.frequency(60), FREQS[60] * 2.0f32.powf(0.5 / 12.0));
+        assert_close(tuning.frequency(61), FREQS[61]);
+        assert_close(tuning.frequency(62), 440.0);
+
+        // A bulk dump tuning every key a semitone down, except for key 69
+        let mut message = vec![0xF0, 0x7E, 0x7F, 0x08, 0x01, 0x00];
+        message.extend_from_slice(b"Semitone down   ");
+        for key in 0..128u8 {
+            match key {
+                69 => message.extend_from_slice(&[0x7F, 0x7F, 0x7F]),
+                _ => message.extend_from_slice(&[key.saturating_sub(1), 0x00, 0x00]),
+            }
+        }
+        message.extend_from_slice(&[0x00, 0xF7]);
+        assert!(tuning.apply_mts_sysex(&message));
+        assert_close(tuning.frequency(60), FREQS[59]);
+        assert_close(tuning.frequency(62), FREQS[61]);
+        assert_close(tuning.frequency(69), FREQS[69]);
+
+        // Other SysEx messages are ignored, such as a GM system on
+        assert!(!tuning.apply_mts_sysex(&[0xF0, 0x7E, 0x7F, 0x09, 0x01, 0xF7]));
+        assert!(!tuning.apply_mts_sysex(&[0xF0, 0x7F, 0x7F, 0x08, 0x02, 0x00, 0x02, 60]));
+    }
+}
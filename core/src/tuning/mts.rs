@@ -0,0 +1,77 @@
+//! Parsers for the MIDI Tuning Standard (MTS) SysEx messages.
+//!
+//! Tuning programs and banks aren't tracked, so every message retunes the
+//! channel it is sent to.
+
+/// The frequency of a 3 byte MTS key frequency, or `None` for the "no change" value
+fn frequency(data: &[u8]) -> Option<f32> {
+    if data == [0x7F, 0x7F, 0x7F] {
+        return None;
+    }
+
+    // A key in 12 tone equal temperament, plus a fraction of a semitone in 14 bits
+    let fraction = ((data[1] as u32) << 7 | data[2] as u32) as f32 / 16384.0;
+    let semitones = data[0] as f32 + fraction;
+    Some(440.0 * 2.0f32.powf((semitones - 69.0) / 12.0))
+}
+
+/// Retunes a list of (key, frequency) changes. Returns false if the message is cut short.
+fn apply_note_changes(data: &[u8], frequencies: &mut [f32; 128]) -> bool {
+    let (count, changes) = match data.split_first() {
+        Some((count, changes)) => (*count as usize, changes),
+        None => return false,
+    };
+    if changes.len() < count * 4 {
+        return false;
+    }
+
+    for change in changes.chunks_exact(4).take(count) {
+        if let Some(frequency) = frequency(&change[1..]) {
+            frequencies[(change[0] & 0x7F) as usize] = frequency;
+        }
+    }
+    true
+}
+
+/// Retunes all 128 keys from a bulk dump. Returns false if the message is cut short.
+fn apply_bulk_dump(data: &[u8], frequencies: &mut [f32; 128]) -> bool {
+    // The tuning name comes before the frequencies
+    let data = match data.get(16..16 + 128 * 3) {
+        Some(data) => data,
+        None => return false,
+    };
+
+    for (key, entry) in data.chunks_exact(3).enumerate() {
+        if let Some(frequency) = frequency(entry) {
+            frequencies[key] = frequency;
+        }
+    }
+    true
+}
+
+/// Applies a SysEx message to the key frequencies if it is an MTS single note
+/// tuning change or bulk tuning dump. The message may include its 0xF0 and 0xF7
+/// bytes. Returns whether the frequencies were changed.
+pub(super) fn apply(message: &[u8], frequencies: &mut [f32; 128]) -> bool {
+    let message = message.strip_prefix(&[0xF0]).unwrap_or(message);
+    let message = message.strip_suffix(&[0xF7]).unwrap_or(message);
+
+    // Universal SysEx header: real time or non real time, device id, MTS sub id
+    let (realtime, data) = match message {
+        [0x7F, _, 0x08, data @ ..] => (true, data),
+        [0x7E, _, 0x08, data @ ..] => (false, data),
+        _ => return false,
+    };
+
+    match (realtime, data) {
+        // Bulk tuning dump, with a tuning program, and optionally a bank
+        (false, [0x01, _program, data @ ..]) => apply_bulk_dump(data, frequencies),
+        (false, [0x04, _bank, _program, data @ ..]) => apply_bulk_dump(data, frequencies),
+
+        // Single note tuning change, with a tuning program, and optionally a bank
+        (true, [0x02, _program, data @ ..]) => apply_note_changes(data, frequencies),
+        (_, [0x07, _bank, _program, data @ ..]) => apply_note_changes(data, frequencies),
+
+        _ => false,
+    }
+}
@@ -0,0 +1,194 @@
+//! Parsers for the Scala scale (.scl) and keyboard mapping (.kbm) formats,
+//! as described at <https://www.huygens-fokker.org/scala/scl_format.html>.
+
+/// The lines of a Scala file, without comments
+fn content_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines().filter(|line| !line.starts_with('!'))
+}
+
+/// The first whitespace separated value of a line, ignoring anything after it
+fn first_value(line: &str) -> &str {
+    line.split_whitespace().next().unwrap_or("")
+}
+
+/// Parses a pitch, either in cents if it contains a period, or as a ratio
+fn parse_pitch(value: &str) -> Result<f64, String> {
+    let invalid = || format!("invalid pitch \"{}\"", value);
+
+    let cents = if value.contains('.') {
+        value.parse::<f64>().map_err(|_| invalid())?
+    } else {
+        let mut parts = value.splitn(2, '/');
+        let numerator = parts.next().unwrap_or("");
+        let numerator = numerator.parse::<u64>().map_err(|_| invalid())?;
+        let denominator = match parts.next() {
+            Some(denominator) => denominator.parse::<u64>().map_err(|_| invalid())?,
+            None => 1,
+        };
+        if numerator == 0 || denominator == 0 {
+            return Err(invalid());
+        }
+        1200.0 * (numerator as f64 / denominator as f64).log2()
+    };
+
+    Ok(cents)
+}
+
+/// The pitches of a scale, in cents above its first degree
+pub(super) struct Scale {
+    /// The pitches of degrees 1 to N, with the last one being the period (usually an octave)
+    cents: Vec<f64>,
+}
+
+impl Scale {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = content_lines(text);
+
+        // The first line is a description, which may be empty
+        lines.next().ok_or("missing description")?;
+
+        let count = lines.next().ok_or("missing note count")?;
+        let count = first_value(count)
+            .parse::<usize>()
+            .map_err(|_| format!("invalid note count \"{}\"", count.trim()))?;
+        if count == 0 {
+            return Err("the scale has no notes".into());
+        }
+
+        let cents = lines
+            .map(first_value)
+            .filter(|value| !value.is_empty())
+            .take(count)
+            .map(parse_pitch)
+            .collect::<Result<Vec<_>, _>>()?;
+        if cents.len() < count {
+            return Err(format!("expected {} notes, found {}", count, cents.len()));
+        }
+
+        Ok(Scale { cents })
+    }
+
+    pub fn len(&self) -> usize {
+        self.cents.len()
+    }
+
+    /// The pitch of a scale degree in cents, which can be outside of the first period
+    fn degree_cents(&self, degree: i64) -> f64 {
+        let len = self.len() as i64;
+        let period = self.cents[self.len() - 1];
+        let octave = degree.div_euclid(len);
+        let index = degree.rem_euclid(len) as usize;
+
+        let cents = if index == 0 {
+            0.0
+        } else {
+            self.cents[index - 1]
+        };
+        octave as f64 * period + cents
+    }
+}
+
+/// The mapping of MIDI keys onto scale degrees
+pub(super) struct KeyboardMapping {
+    first_key: u8,
+    last_key: u8,
+    middle_key: u8,
+    reference_key: u8,
+    reference_frequency: f64,
+    /// The scale degree that the mapping repeats at
+    octave_degree: i64,
+    /// The scale degree of each key in the mapping, starting at the middle key.
+    /// If empty, consecutive keys play consecutive scale degrees.
+    degrees: Vec<Option<i64>>,
+}
+
+impl KeyboardMapping {
+    /// Maps the scale's first degree onto key 60, with key 69 at 440Hz
+    pub fn linear(scale_len: usize) -> Self {
+        KeyboardMapping {
+            first_key: 0,
+            last_key: 127,
+            middle_key: 60,
+            reference_key: 69,
+            reference_frequency: 440.0,
+            octave_degree: scale_len as i64,
+            degrees: Vec::new(),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut values = content_lines(text)
+            .map(first_value)
+            .filter(|value| !value.is_empty());
+
+        let mut next = |name: &str| values.next().ok_or(format!("missing {}", name));
+        fn parse<T: std::str::FromStr>(value: &str, name: &str) -> Result<T, String> {
+            value
+                .parse::<T>()
+                .map_err(|_| format!("invalid {} \"{}\"", name, value))
+        }
+
+        let size: usize = parse(next("map size")?, "map size")?;
+        let first_key = parse(next("first key")?, "first key")?;
+        let last_key = parse(next("last key")?, "last key")?;
+        let middle_key = parse(next("middle key")?, "middle key")?;
+        let reference_key = parse(next("reference key")?, "reference key")?;
+        let reference_frequency = parse(next("reference frequency")?, "reference frequency")?;
+        let octave_degree = parse(next("octave degree")?, "octave degree")?;
+
+        // Missing entries at the end of the mapping are unmapped
+        let mut degrees = Vec::with_capacity(size);
+        for _ in 0..size {
+            let degree = match values.next() {
+                Some("x") | None => None,
+                Some(value) => Some(parse(value, "scale degree")?),
+            };
+            degrees.push(degree);
+        }
+
+        let keys = [first_key, last_key, middle_key, reference_key];
+        if keys.iter().any(|key: &u8| *key > 127) {
+            return Err("keys must be between 0 and 127".into());
+        }
+
+        Ok(KeyboardMapping {
+            first_key,
+            last_key,
+            middle_key,
+            reference_key,
+            reference_frequency,
+            octave_degree,
+            degrees,
+        })
+    }
+
+    /// The pitch of a key in cents above the middle key, if it is mapped
+    fn key_cents(&self, scale: &Scale, key: u8) -> Option<f64> {
+        let offset = key as i64 - self.middle_key as i64;
+        if self.degrees.is_empty() {
+            return Some(scale.degree_cents(offset));
+        }
+
+        let size = self.degrees.len() as i64;
+        let octave = offset.div_euclid(size);
+        let degree = self.degrees[offset.rem_euclid(size) as usize]?;
+        Some(octave as f64 * scale.degree_cents(self.octave_degree) + scale.degree_cents(degree))
+    }
+
+    /// Retunes the mapped keys. Keys that aren't mapped keep their frequency.
+    pub fn apply(&self, scale: &Scale, frequencies: &mut [f32; 128]) -> Result<(), String> {
+        let reference_cents = self
+            .key_cents(scale, self.reference_key)
+            .ok_or("the reference key is unmapped")?;
+
+        for key in self.first_key..=self.last_key.min(127) {
+            if let Some(cents) = self.key_cents(scale, key) {
+                let frequency =
+                    self.reference_frequency * 2.0f64.powf((cents - reference_cents) / 1200.0);
+                frequencies[key as usize] = frequency as f32;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,9 +1,21 @@
 mod envelopes;
 pub use envelopes::*;
 
+mod filter;
+pub use filter::*;
+
+mod lfo;
+pub use lfo::*;
+
+mod ramp;
+pub use ramp::*;
+
 mod simd;
 pub use simd::*;
 
+mod velocity_curve;
+pub use velocity_curve::*;
+
 mod simdvoice;
 pub use simdvoice::*;
 
@@ -25,22 +37,86 @@ pub use sampler::*;
 mod control;
 pub use control::*;
 
+use std::sync::Arc;
+
+use crate::{channel::PanLaw, tuning::Tuning};
+
+#[derive(Debug, Clone)]
 pub struct VoiceControlData {
     pub voice_pitch_multiplier: f32,
+
+    /// The channel volume gain
+    pub volume: f32,
+    /// The channel expression gain
+    pub expression: f32,
+    /// The channel pan, between -1 (left) and 1 (right)
+    pub pan: f32,
+    /// How the channel pan is converted into left and right gains
+    pub pan_law: PanLaw,
+    /// The modulation wheel, between 0 and 1, for scaling LFO depths
+    pub modulation: f32,
+    /// The channel pressure (aftertouch), between 0 and 1
+    pub channel_pressure: f32,
+    /// The polyphonic aftertouch of the key, between 0 and 1, set by each key
+    pub key_pressure: f32,
+    /// The velocity curve of the channel, which overrides the soundfonts' curves
+    pub velocity_curve: Option<VelocityCurve>,
+    /// The key frequencies of the channel, if it isn't in equal temperament
+    pub tuning: Option<Arc<Tuning>>,
+
+    /// Whether the channel is in mono mode, where legato notes move the pitch
+    /// of the sounding voices instead of spawning new ones
+    pub mono: bool,
+    /// The speed of portamento glides in semitones per sample, or infinity
+    /// when the portamento pedal is off
+    pub portamento_rate: f32,
+    /// The pitch offset of the key's voices in semitones, set by each key
+    /// when legato notes play another key through them
+    pub pitch_offset: f32,
+    /// The pitch offset that new voices glide from in semitones, set by each key
+    pub glide_from: f32,
+    /// Whether the channel plays drum kits, where unlooped samples ignore note offs
+    pub percussion: bool,
 }
 
 impl VoiceControlData {
     pub fn new_defaults() -> Self {
         VoiceControlData {
             voice_pitch_multiplier: 1.0,
+            volume: 1.0,
+            expression: 1.0,
+            pan: 0.0,
+            pan_law: PanLaw::default(),
+            modulation: 0.0,
+            channel_pressure: 0.0,
+            key_pressure: 0.0,
+            velocity_curve: None,
+            tuning: None,
+            mono: false,
+            portamento_rate: f32::INFINITY,
+            pitch_offset: 0.0,
+            glide_from: 0.0,
+            percussion: false,
         }
     }
+
+    /// The pressure applied to the voices of a key, from the channel pressure
+    /// or the key's own aftertouch, whichever is stronger
+    pub fn pressure(&self) -> f32 {
+        self.channel_pressure.max(self.key_pressure)
+    }
 }
 
 pub trait VoiceGeneratorBase: Sync + Send {
     fn ended(&self) -> bool;
     fn signal_release(&mut self);
     fn process_controls(&mut self, control: &VoiceControlData);
+
+    /// The current level of the amplitude envelopes within the generator, between
+    /// 0 and 1. Generators without an amplitude envelope are at full level.
+    fn envelope_level(&self) -> f32 {
+        1.0
+    }
 }
 
 pub trait VoiceSampleGenerator: VoiceGeneratorBase {
@@ -51,4 +127,14 @@ pub trait Voice: VoiceSampleGenerator + Send + Sync {
     fn is_releasing(&self) -> bool;
 
     fn velocity(&self) -> u8;
+
+    /// The choke group that stops the voice when one of its notes is played, if any
+    fn off_by(&self) -> Option<u32>;
+
+    /// Stops the voice with a quick linear fade over `fade_samples` samples of the
+    /// interleaved output, so that it ends without a click
+    fn signal_kill(&mut self, fade_samples: usize);
+
+    /// Whether the voice was killed, and is fading out
+    fn is_killed(&self) -> bool;
 }
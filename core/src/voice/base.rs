@@ -1,4 +1,4 @@
-use crate::voice::VoiceControlData;
+use crate::{helpers::prepapre_cache_vec, voice::VoiceControlData};
 
 use super::{Voice, VoiceGeneratorBase, VoiceSampleGenerator};
 
@@ -6,7 +6,17 @@ use super::{Voice, VoiceGeneratorBase, VoiceSampleGenerator};
 pub struct VoiceBase<T: Send + Sync + VoiceSampleGenerator> {
     sample_generator: T,
     releasing: bool,
+    one_shot: bool,
     velocity: u8,
+    off_by: Option<u32>,
+    kill: Option<KillFade>,
+}
+
+/// A quick linear fade out of a voice that is being stopped
+struct KillFade {
+    gain: f32,
+    step: f32, // Gain change per sample
+    buffer: Vec<f32>,
 }
 
 impl<T: Send + Sync + VoiceSampleGenerator> VoiceBase<T> {
@@ -14,9 +24,30 @@ impl<T: Send + Sync + VoiceSampleGenerator> VoiceBase<T> {
         VoiceBase {
             sample_generator: sample_generator,
             releasing: false,
+            one_shot: false,
+            velocity,
+            off_by: None,
+            kill: None,
+        }
+    }
+
+    /// Creates a voice that ignores note offs, and keeps playing until its generator ends.
+    pub fn new_one_shot(velocity: u8, sample_generator: T) -> VoiceBase<T> {
+        VoiceBase {
+            sample_generator,
+            releasing: false,
+            one_shot: true,
             velocity,
+            off_by: None,
+            kill: None,
         }
     }
+
+    /// Sets the choke group that stops the voice, if any
+    pub fn with_off_by(mut self, off_by: Option<u32>) -> Self {
+        self.off_by = off_by;
+        self
+    }
 }
 
 impl<T> VoiceGeneratorBase for VoiceBase<T>
@@ -25,18 +56,31 @@ where
 {
     #[inline(always)]
     fn ended(&self) -> bool {
-        self.sample_generator.ended()
+        match &self.kill {
+            Some(kill) if kill.gain <= 0.0 => true,
+            _ => self.sample_generator.ended(),
+        }
     }
 
     #[inline(always)]
     fn signal_release(&mut self) {
         self.releasing = true;
-        self.sample_generator.signal_release()
+        if !self.one_shot {
+            self.sample_generator.signal_release()
+        }
     }
 
     fn process_controls(&mut self, control: &VoiceControlData) {
         self.sample_generator.process_controls(control)
     }
+
+    fn envelope_level(&self) -> f32 {
+        let level = self.sample_generator.envelope_level();
+        match &self.kill {
+            Some(kill) => level * kill.gain,
+            None => level,
+        }
+    }
 }
 
 impl<T> VoiceSampleGenerator for VoiceBase<T>
@@ -45,7 +89,18 @@ where
 {
     #[inline(always)]
     fn render_to(&mut self, buffer: &mut [f32]) {
-        self.sample_generator.render_to(buffer)
+        let kill = match &mut self.kill {
+            Some(kill) => kill,
+            None => return self.sample_generator.render_to(buffer),
+        };
+
+        // A voice being killed renders on its own, so that the fade only applies to it
+        prepapre_cache_vec(&mut kill.buffer, buffer.len(), 0.0);
+        self.sample_generator.render_to(&mut kill.buffer);
+        for (out, sample) in buffer.iter_mut().zip(kill.buffer.iter()) {
+            *out += sample * kill.gain;
+            kill.gain = (kill.gain - kill.step).max(0.0);
+        }
     }
 }
 
@@ -62,4 +117,25 @@ where
     fn velocity(&self) -> u8 {
         self.velocity
     }
+
+    #[inline(always)]
+    fn off_by(&self) -> Option<u32> {
+        self.off_by
+    }
+
+    #[inline(always)]
+    fn is_killed(&self) -> bool {
+        self.kill.is_some()
+    }
+
+    fn signal_kill(&mut self, fade_samples: usize) {
+        self.releasing = true;
+        if self.kill.is_none() {
+            self.kill = Some(KillFade {
+                gain: 1.0,
+                step: 1.0 / fade_samples.max(1) as f32,
+                buffer: Vec::new(),
+            });
+        }
+    }
 }
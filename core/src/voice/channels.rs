@@ -41,6 +41,10 @@ where
     fn process_controls(&mut self, control: &VoiceControlData) {
         self.generator.process_controls(control)
     }
+
+    fn envelope_level(&self) -> f32 {
+        self.generator.envelope_level()
+    }
 }
 
 impl<S, G> SIMDVoiceGenerator<S, SIMDSampleStereo<S>> for SIMDVoiceMonoToStereo<S, G>
@@ -2,7 +2,7 @@ use simdeez::Simd;
 
 use crate::voice::VoiceControlData;
 
-use super::{SIMDSampleMono, SIMDVoiceGenerator, VoiceGeneratorBase};
+use super::{SIMDSampleMono, SIMDSampleStereo, SIMDVoiceGenerator, VoiceGeneratorBase};
 
 pub struct SIMDConstant<S: Simd> {
     values: S::Vf32,
@@ -33,3 +33,35 @@ impl<S: Simd> SIMDVoiceGenerator<S, SIMDSampleMono<S>> for SIMDConstant<S> {
         SIMDSampleMono(self.values)
     }
 }
+
+pub struct SIMDStereoConstant<S: Simd> {
+    left: S::Vf32,
+    right: S::Vf32,
+}
+
+impl<S: Simd> SIMDStereoConstant<S> {
+    pub fn new(left: f32, right: f32) -> SIMDStereoConstant<S> {
+        unsafe {
+            SIMDStereoConstant {
+                left: S::set1_ps(left),
+                right: S::set1_ps(right),
+            }
+        }
+    }
+}
+
+impl<S: Simd> VoiceGeneratorBase for SIMDStereoConstant<S> {
+    fn ended(&self) -> bool {
+        false
+    }
+
+    fn signal_release(&mut self) {}
+
+    fn process_controls(&mut self, _control: &VoiceControlData) {}
+}
+
+impl<S: Simd> SIMDVoiceGenerator<S, SIMDSampleStereo<S>> for SIMDStereoConstant<S> {
+    fn next_sample(&mut self) -> SIMDSampleStereo<S> {
+        SIMDSampleStereo(self.left, self.right)
+    }
+}
@@ -42,3 +42,51 @@ impl<S: Simd> SIMDVoiceGenerator<S, SIMDSampleMono<S>> for SIMDVoiceControl<S> {
         SIMDSampleMono(self.values)
     }
 }
+
+/// A control value applied to a voice with a depth of its own, such as how far
+/// aftertouch moves the filter cutoff. The depth can be a tuple, for controls
+/// that combine several sources. Like [`SIMDVoiceControl`], the value is only
+/// computed when the controls change, so the mapping can be expensive.
+pub struct SIMDVoiceModulator<S: Simd, D: Copy = f32> {
+    values: S::Vf32,
+    depth: D,
+    update: fn(&VoiceControlData, D) -> f32,
+}
+
+impl<S: Simd, D: Copy> SIMDVoiceModulator<S, D> {
+    pub fn new(
+        control: &VoiceControlData,
+        depth: D,
+        update: fn(&VoiceControlData, D) -> f32,
+    ) -> SIMDVoiceModulator<S, D> {
+        unsafe {
+            SIMDVoiceModulator {
+                values: S::set1_ps((update)(control, depth)),
+                depth,
+                update,
+            }
+        }
+    }
+}
+
+impl<S: Simd, D: Copy + Send + Sync> VoiceGeneratorBase for SIMDVoiceModulator<S, D> {
+    fn ended(&self) -> bool {
+        false
+    }
+
+    fn signal_release(&mut self) {}
+
+    fn process_controls(&mut self, control: &VoiceControlData) {
+        unsafe {
+            self.values = S::set1_ps((self.update)(control, self.depth));
+        }
+    }
+}
+
+impl<S: Simd, D: Copy + Send + Sync> SIMDVoiceGenerator<S, SIMDSampleMono<S>>
+    for SIMDVoiceModulator<S, D>
+{
+    fn next_sample(&mut self) -> SIMDSampleMono<S> {
+        SIMDSampleMono(self.values)
+    }
+}
@@ -36,6 +36,107 @@ impl EnvelopeStage {
     }
 }
 
+/// The shape of an envelope stage's transition from its start value to its target
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum EnvelopeCurveType {
+    /// A straight line
+    #[default]
+    Linear,
+    /// An exponential approach to the target. When falling to silence, this is
+    /// linear in decibels over a 96dB range.
+    Exponential,
+    /// Moves quickly at the start of the stage, then slows down towards the target
+    Convex,
+    /// Moves slowly at the start of the stage, then speeds up towards the target
+    Concave,
+}
+
+// The exponential curve falls by 96dB over the stage, e^(-rate) = 10^(-96/20)
+const EXPONENTIAL_CURVE_RATE: f32 = 11.052_408;
+
+impl EnvelopeCurveType {
+    /// Maps the linear progress through a stage (0-1) onto the curve's lerp factor (0-1)
+    pub fn shape(&self, progress: f32) -> f32 {
+        match self {
+            EnvelopeCurveType::Linear => progress,
+            EnvelopeCurveType::Exponential => {
+                let falloff = (-EXPONENTIAL_CURVE_RATE * progress).exp();
+                (1.0 - falloff) / (1.0 - (-EXPONENTIAL_CURVE_RATE).exp())
+            }
+            EnvelopeCurveType::Convex => 1.0 - (1.0 - progress) * (1.0 - progress),
+            EnvelopeCurveType::Concave => progress * progress,
+        }
+    }
+}
+
+/// Applies a curve to the progress of a SIMD array through a stage.
+///
+/// The exponential curve has no direct SIMD equivalent, so its falloff is
+/// tracked separately and multiplied by a constant step as the time increments.
+struct SIMDCurve<T: Simd> {
+    curve: EnvelopeCurveType,
+    one_simd: T::Vf32,
+    falloff_simd: T::Vf32, // e^(-rate * progress) for each value in the SIMD array
+    falloff_increment_simd: T::Vf32, // The falloff step over the SIMD width
+    falloff_step: f32,     // The falloff step over a single sample
+    falloff_scale_simd: T::Vf32, // Normalizes the curve to reach exactly 1 at the end
+}
+
+impl<T: Simd> SIMDCurve<T> {
+    fn new(curve: EnvelopeCurveType, start_offset: u32, stage_end_time: u32) -> Self {
+        let falloff_step = (-EXPONENTIAL_CURVE_RATE / stage_end_time as f32).exp();
+
+        unsafe {
+            let mut falloff_simd = T::set1_ps(0.0);
+            for i in 0..T::VF32_WIDTH {
+                falloff_simd[i] = falloff_step.powi((start_offset as usize + i) as i32);
+            }
+
+            SIMDCurve {
+                curve,
+                one_simd: T::set1_ps(1.0),
+                falloff_simd,
+                falloff_increment_simd: T::set1_ps(falloff_step.powi(T::VF32_WIDTH as i32)),
+                falloff_step,
+                falloff_scale_simd: T::set1_ps(1.0 / (1.0 - (-EXPONENTIAL_CURVE_RATE).exp())),
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn increment(&mut self) {
+        if self.curve == EnvelopeCurveType::Exponential {
+            self.falloff_simd *= self.falloff_increment_simd;
+        }
+    }
+
+    #[inline(always)]
+    fn increment_by(&mut self, by: u32) {
+        if self.curve == EnvelopeCurveType::Exponential {
+            self.falloff_simd *= unsafe { T::set1_ps(self.falloff_step.powi(by as i32)) };
+        }
+    }
+
+    fn shape(&self, progress: f32) -> f32 {
+        self.curve.shape(progress)
+    }
+
+    #[inline(always)]
+    fn shape_simd(&self, progress: T::Vf32) -> T::Vf32 {
+        match self.curve {
+            EnvelopeCurveType::Linear => progress,
+            EnvelopeCurveType::Exponential => {
+                (self.one_simd - self.falloff_simd) * self.falloff_scale_simd
+            }
+            EnvelopeCurveType::Convex => {
+                let remaining = self.one_simd - progress;
+                self.one_simd - remaining * remaining
+            }
+            EnvelopeCurveType::Concave => progress * progress,
+        }
+    }
+}
+
 // The lerp equation is `start + (end - start) * factor`
 // We store: start, length (= end - start)
 struct SIMDLerper<T: Simd> {
@@ -152,6 +253,12 @@ pub enum EnvelopePart {
         duration: u32, // Duration in samples
         cache_index: usize,
     },
+    /// Like `Lerp`, but following a curve, and without a precomputed cache
+    Curve {
+        target: f32,   // Target value by the end of the envelope part
+        duration: u32, // Duration in samples
+        curve: EnvelopeCurveType,
+    },
     Hold(f32),
 }
 
@@ -164,6 +271,14 @@ impl EnvelopePart {
         }
     }
 
+    pub fn curve(target: f32, duration: u32, curve: EnvelopeCurveType) -> EnvelopePart {
+        EnvelopePart::Curve {
+            target,
+            duration,
+            curve,
+        }
+    }
+
     pub fn hold(value: f32) -> EnvelopePart {
         EnvelopePart::Hold(value)
     }
@@ -179,9 +294,59 @@ pub struct EnvelopeDescriptor {
     pub decay: f32,           // Seconds
     pub sustain_percent: f32, // % (0-1)
     pub release: f32,         // Seconds
+    pub attack_curve: EnvelopeCurveType,
+    pub decay_curve: EnvelopeCurveType,
+    pub release_curve: EnvelopeCurveType,
+}
+
+/// Scales the stage times of an envelope by the key and velocity of each voice
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct EnvelopeModulation {
+    pub key_to_hold: f32,    // Timecents per key, shortening the hold above key 60
+    pub key_to_decay: f32,   // Timecents per key, shortening the decay above key 60
+    pub vel_to_delay: f32,   // Seconds added at velocity 127
+    pub vel_to_attack: f32,  // Seconds added at velocity 127
+    pub vel_to_hold: f32,    // Seconds added at velocity 127
+    pub vel_to_decay: f32,   // Seconds added at velocity 127
+    pub vel_to_release: f32, // Seconds added at velocity 127
+}
+
+impl EnvelopeModulation {
+    /// Whether the modulation has no effect, in which case the same envelope
+    /// parameters can be shared between every key and velocity.
+    pub fn is_empty(&self) -> bool {
+        *self == EnvelopeModulation::default()
+    }
 }
 
 impl EnvelopeDescriptor {
+    /// Applies the key and velocity modulation of a voice to the stage times
+    pub fn modulated(
+        &self,
+        modulation: &EnvelopeModulation,
+        key: u8,
+        vel: u8,
+    ) -> EnvelopeDescriptor {
+        let key_scale = |timecents: f32| 2.0f32.powf(timecents * (60.0 - key as f32) / 1200.0);
+        let vel = vel as f32 / 127.0;
+        let time = |seconds: f32, vel_to: f32| (seconds + vel_to * vel).max(0.0);
+
+        EnvelopeDescriptor {
+            delay: time(self.delay, modulation.vel_to_delay),
+            attack: time(self.attack, modulation.vel_to_attack),
+            hold: time(
+                self.hold * key_scale(modulation.key_to_hold),
+                modulation.vel_to_hold,
+            ),
+            decay: time(
+                self.decay * key_scale(modulation.key_to_decay),
+                modulation.vel_to_decay,
+            ),
+            release: time(self.release, modulation.vel_to_release),
+            ..*self
+        }
+    }
+
     pub fn to_envelope_params(&self, samplerate: u32) -> EnvelopeParameters {
         let samplerate = samplerate as f32;
 
@@ -197,22 +362,7 @@ impl EnvelopeDescriptor {
 
         EnvelopeParameters {
             start: self.start_percent,
-            parts: [
-                // Delay
-                EnvelopePart::lerp(0, self.start_percent, (self.delay * samplerate) as u32),
-                // Attack
-                EnvelopePart::lerp(1, 1.0, (self.attack * samplerate) as u32),
-                // Hold
-                EnvelopePart::lerp(2, 1.0, (self.hold * samplerate) as u32),
-                // Decay
-                EnvelopePart::lerp(3, self.sustain_percent, (self.decay * samplerate) as u32),
-                // Sustain
-                EnvelopePart::hold(self.sustain_percent),
-                // Release
-                EnvelopePart::lerp(4, 0.0, (self.release * samplerate) as u32),
-                // Finished
-                EnvelopePart::hold(0.0),
-            ],
+            parts: self.parts(samplerate, true),
             caches: Box::new([
                 gen_lerp(
                     self.start_percent,
@@ -230,8 +380,52 @@ impl EnvelopeDescriptor {
             ]),
         }
     }
+
+    /// Builds the parameters without the precomputed caches of the linear stages,
+    /// for envelopes that are built for every key and velocity
+    pub fn to_uncached_envelope_params(&self, samplerate: u32) -> EnvelopeParameters {
+        EnvelopeParameters {
+            start: self.start_percent,
+            parts: self.parts(samplerate as f32, false),
+            caches: Box::new([]),
+        }
+    }
+
+    /// The envelope parts, where linear stages use their precomputed cache if `cached` is set
+    fn parts(&self, samplerate: f32, cached: bool) -> [EnvelopePart; 7] {
+        let stage = |cache_index, target, seconds: f32, curve| {
+            let duration = (seconds * samplerate) as u32;
+            match curve {
+                EnvelopeCurveType::Linear if cached => {
+                    EnvelopePart::lerp(cache_index, target, duration)
+                }
+                curve => EnvelopePart::curve(target, duration, curve),
+            }
+        };
+        let linear = EnvelopeCurveType::Linear;
+
+        [
+            // Delay
+            stage(0, self.start_percent, self.delay, linear),
+            // Attack
+            stage(1, 1.0, self.attack, self.attack_curve),
+            // Hold
+            stage(2, 1.0, self.hold, linear),
+            // Decay
+            stage(3, self.sustain_percent, self.decay, self.decay_curve),
+            // Sustain
+            EnvelopePart::hold(self.sustain_percent),
+            // Release
+            stage(4, 0.0, self.release, self.release_curve),
+            // Finished
+            EnvelopePart::hold(0.0),
+        ]
+    }
 }
 
+// Whether linear stages play their precomputed cache rather than being lerped
+const READ_CACHES: bool = false;
+
 /// The raw envelope parameters used to generate the envelope.
 /// Is a separate struct to EnvelopeDescriptor for performance reasons.
 /// Use EnvelopeDescriptor to generate the EnvelopeParameters struct.
@@ -243,7 +437,7 @@ pub struct EnvelopeParameters {
 }
 
 impl EnvelopeParameters {
-    fn get_cache_at(&self, index: usize) -> &Box<[f32]> {
+    fn get_cache_at(&self, index: usize) -> &[f32] {
         &self.caches[index]
     }
 
@@ -265,7 +459,7 @@ impl EnvelopeParameters {
                     self.get_stage_data(stage.next_stage(), target)
                 } else {
                     let cache_index = *cache_index;
-                    if false && self.get_cache_at(cache_index)[0] == start_amp {
+                    if READ_CACHES && self.get_cache_at(cache_index)[0] == start_amp {
                         let data = StageData::Cache {
                             cache_index,
                             length: self.get_cache_at(cache_index).len(),
@@ -276,10 +470,8 @@ impl EnvelopeParameters {
                             stage_data: data,
                         }
                     } else {
-                        let data = StageData::Lerp(
-                            SIMDLerper::new(start_amp, target),
-                            StageTime::new(0, duration),
-                        );
+                        let data =
+                            StageData::lerp(start_amp, target, duration, EnvelopeCurveType::Linear);
                         VoiceEnvelopeState {
                             current_stage: stage,
                             stage_data: data,
@@ -287,6 +479,20 @@ impl EnvelopeParameters {
                     }
                 }
             }
+            EnvelopePart::Curve {
+                target,
+                duration,
+                curve,
+            } => {
+                if *duration == 0 {
+                    self.get_stage_data(stage.next_stage(), *target)
+                } else {
+                    VoiceEnvelopeState {
+                        current_stage: stage,
+                        stage_data: StageData::lerp(start_amp, *target, *duration, *curve),
+                    }
+                }
+            }
             EnvelopePart::Hold(value) => {
                 let data = StageData::Constant(unsafe { T::set1_ps(*value) });
                 VoiceEnvelopeState {
@@ -299,7 +505,7 @@ impl EnvelopeParameters {
 }
 
 enum StageData<T: Simd> {
-    Lerp(SIMDLerper<T>, StageTime<T>),
+    Lerp(SIMDLerper<T>, StageTime<T>, SIMDCurve<T>),
     Cache {
         time: usize,
         length: usize,
@@ -308,6 +514,16 @@ enum StageData<T: Simd> {
     Constant(T::Vf32),
 }
 
+impl<T: Simd> StageData<T> {
+    fn lerp(start_amp: f32, target: f32, duration: u32, curve: EnvelopeCurveType) -> Self {
+        StageData::Lerp(
+            SIMDLerper::new(start_amp, target),
+            StageTime::new(0, duration),
+            SIMDCurve::new(curve, 0, duration),
+        )
+    }
+}
+
 struct VoiceEnvelopeState<T: Simd> {
     current_stage: EnvelopeStage,
     stage_data: StageData<T>,
@@ -327,8 +543,9 @@ impl<T: Simd> SIMDVoiceEnvelope<T> {
 
     pub fn get_value_at_current_time(&self) -> f32 {
         match &self.state.stage_data {
-            StageData::Lerp(lerper, stage_time) => {
-                lerper.lerp(stage_time.simd_array_start_f32() / stage_time.stage_end_time_f32)
+            StageData::Lerp(lerper, stage_time, curve) => {
+                let progress = stage_time.simd_array_start_f32() / stage_time.stage_end_time_f32;
+                lerper.lerp(curve.shape(progress))
             }
             StageData::Constant(constant) => constant[0],
             StageData::Cache {
@@ -356,8 +573,9 @@ impl<T: Simd> SIMDVoiceEnvelope<T> {
 
     fn increment_time_by(&mut self, increment: u32) {
         match &mut self.state.stage_data {
-            StageData::Lerp(_, stage_time) => {
+            StageData::Lerp(_, stage_time, curve) => {
                 stage_time.increment_by(increment);
+                curve.increment_by(increment);
             }
             StageData::Constant(_) => {}
             StageData::Cache { time, .. } => {
@@ -373,7 +591,7 @@ impl<T: Simd> SIMDVoiceEnvelope<T> {
             values[i] = sample;
             self.increment_time_by(1);
             let should_progress = match &mut self.state.stage_data {
-                StageData::Lerp(_, stage_time) => {
+                StageData::Lerp(_, stage_time, _) => {
                     stage_time.is_ending() && !stage_time.is_intersecting_end()
                 }
                 StageData::Constant(_) => false,
@@ -398,12 +616,16 @@ impl<T: Simd> VoiceGeneratorBase for SIMDVoiceEnvelope<T> {
     }
 
     fn process_controls(&mut self, _control: &VoiceControlData) {}
+
+    fn envelope_level(&self) -> f32 {
+        self.get_value_at_current_time()
+    }
 }
 
 impl<T: Simd> SIMDVoiceGenerator<T, SIMDSampleMono<T>> for SIMDVoiceEnvelope<T> {
     fn next_sample(&mut self) -> SIMDSampleMono<T> {
         match &mut self.state.stage_data {
-            StageData::Lerp(lerper, stage_time) => {
+            StageData::Lerp(lerper, stage_time, curve) => {
                 if stage_time.is_ending() {
                     if stage_time.is_intersecting_end() {
                         // It is ended, and the SIMD array intersects the border of the envelope part.
@@ -417,8 +639,10 @@ impl<T: Simd> SIMDVoiceGenerator<T, SIMDSampleMono<T>> for SIMDVoiceEnvelope<T>
                     }
                 } else {
                     // No special conditions happening, return the next entire simd array lerped
-                    let values = lerper.lerp_simd(stage_time.progress_simd_array());
+                    let values =
+                        lerper.lerp_simd(curve.shape_simd(stage_time.progress_simd_array()));
                     stage_time.increment();
+                    curve.increment();
                     SIMDSampleMono(values)
                 }
             }
@@ -573,55 +797,166 @@ mod tests {
 
         simd_runtime_generate!(
             fn run() {
-                let mut vec = Vec::new();
+                let curves = [
+                    EnvelopeCurveType::Linear,
+                    EnvelopeCurveType::Exponential,
+                    EnvelopeCurveType::Convex,
+                    EnvelopeCurveType::Concave,
+                ];
+
+                for curve in curves.iter().copied() {
+                    let mut vec = Vec::new();
+
+                    let descriptor = EnvelopeDescriptor {
+                        start_percent: 0.5,
+                        delay: 0.0,
+                        attack: 15.0,
+                        hold: 0.0,
+                        decay: 17.0,
+                        sustain_percent: 0.4,
+                        release: 16.0,
+                        attack_curve: curve,
+                        decay_curve: curve,
+                        release_curve: curve,
+                    };
+                    let params = Arc::new(descriptor.to_envelope_params(1));
+
+                    let mut env = SIMDVoiceEnvelope::<S>::new(params);
+
+                    let mut i = 0;
+                    while i < 48 {
+                        push_simd_to_vec::<S>(&mut vec, env.next_sample().0);
+                        i += S::VF32_WIDTH;
+                    }
+                    env.signal_release();
+                    assert_eq!(env.current_stage(), &EnvelopeStage::Release);
+                    while i < 48 + 32 {
+                        push_simd_to_vec::<S>(&mut vec, env.next_sample().0);
+                        i += S::VF32_WIDTH;
+                    }
+
+                    let mut expected_vec = Vec::new();
+
+                    for i in 0..15 {
+                        expected_vec.push(lerp(0.5, 1.0, curve.shape(i as f32 / 15.0)));
+                    }
+                    for i in 0..17 {
+                        expected_vec.push(lerp(1.0, 0.4, curve.shape(i as f32 / 17.0)));
+                    }
+                    for _ in 0..16 {
+                        expected_vec.push(0.4);
+                    }
+                    for i in 0..16 {
+                        expected_vec.push(lerp(0.4, 0.0, curve.shape(i as f32 / 16.0)));
+                    }
+                    for _ in 0..16 {
+                        expected_vec.push(0.0);
+                    }
+
+                    if curve == EnvelopeCurveType::Exponential {
+                        // The SIMD path steps the exponential falloff rather than computing it
+                        for (value, expected) in vec.iter().zip(expected_vec.iter()) {
+                            assert!((value - expected).abs() < 0.0001);
+                        }
+                    } else {
+                        assert_eq!(vec, expected_vec);
+                    }
+                }
+
+                // Each curve starts and ends in the same place, with the
+                // exponential and convex curves ahead of the linear one
+                for curve in curves.iter() {
+                    assert_eq!(curve.shape(0.0), 0.0);
+                    assert!((curve.shape(1.0) - 1.0).abs() < 0.0001);
+                }
+                assert!(EnvelopeCurveType::Exponential.shape(0.25) > 0.9);
+                assert!(EnvelopeCurveType::Convex.shape(0.25) > 0.25);
+                assert!(EnvelopeCurveType::Concave.shape(0.25) < 0.25);
+            }
+        );
+
+        run_runtime_select();
+    }
 
+    #[test]
+    fn test_uncached_envelope() {
+        simd_runtime_generate!(
+            fn run() {
                 let descriptor = EnvelopeDescriptor {
                     start_percent: 0.5,
-                    delay: 0.0,
+                    delay: 3.0,
                     attack: 15.0,
-                    hold: 0.0,
+                    hold: 5.0,
                     decay: 17.0,
                     sustain_percent: 0.4,
                     release: 16.0,
+                    attack_curve: EnvelopeCurveType::Linear,
+                    decay_curve: EnvelopeCurveType::Linear,
+                    release_curve: EnvelopeCurveType::Exponential,
                 };
-                let params = Arc::new(descriptor.to_envelope_params(1));
-
-                let mut env = SIMDVoiceEnvelope::<S>::new(params);
-
-                let mut i = 0;
-                while i < 48 {
-                    push_simd_to_vec::<S>(&mut vec, env.next_sample().0);
-                    i += S::VF32_WIDTH;
-                }
-                env.signal_release();
-                assert_eq!(env.current_stage(), &EnvelopeStage::Release);
-                while i < 48 + 32 {
-                    push_simd_to_vec::<S>(&mut vec, env.next_sample().0);
-                    i += S::VF32_WIDTH;
-                }
 
-                let mut expected_vec = Vec::new();
-
-                for i in 0..15 {
-                    expected_vec.push(lerp(0.5, 1.0, i as f32 / 15.0));
-                }
-                for i in 0..17 {
-                    expected_vec.push(lerp(1.0, 0.4, i as f32 / 17.0));
-                }
-                for _ in 0..16 {
-                    expected_vec.push(0.4);
-                }
-                for i in 0..16 {
-                    expected_vec.push(lerp(0.4, 0.0, i as f32 / 16.0));
-                }
-                for _ in 0..16 {
-                    expected_vec.push(0.0);
-                }
+                let render = |params: EnvelopeParameters| {
+                    let mut env = SIMDVoiceEnvelope::<S>::new(Arc::new(params));
+                    let mut vec = Vec::new();
+                    for i in 0..96 / S::VF32_WIDTH {
+                        if i == 64 / S::VF32_WIDTH {
+                            env.signal_release();
+                        }
+                        let values = env.next_sample().0;
+                        for j in 0..S::VF32_WIDTH {
+                            vec.push(values[j]);
+                        }
+                    }
+                    vec
+                };
 
-                assert_eq!(vec, expected_vec);
+                // Envelopes without caches play the same as the cached ones
+                let cached = render(descriptor.to_envelope_params(1));
+                let uncached = render(descriptor.to_uncached_envelope_params(1));
+                assert_eq!(cached, uncached);
             }
         );
 
         run_runtime_select();
     }
+
+    #[test]
+    fn test_envelope_modulation() {
+        let descriptor = EnvelopeDescriptor {
+            start_percent: 0.0,
+            delay: 0.0,
+            attack: 0.5,
+            hold: 1.0,
+            decay: 2.0,
+            sustain_percent: 0.5,
+            release: 0.25,
+            attack_curve: EnvelopeCurveType::Linear,
+            decay_curve: EnvelopeCurveType::Linear,
+            release_curve: EnvelopeCurveType::Linear,
+        };
+        let modulation = EnvelopeModulation {
+            key_to_hold: 100.0,
+            key_to_decay: 50.0,
+            vel_to_attack: -0.5,
+            vel_to_release: 1.0,
+            ..Default::default()
+        };
+        assert!(!modulation.is_empty());
+        assert!(EnvelopeModulation::default().is_empty());
+
+        // Key 60 and velocity 0 are left unchanged
+        let unchanged = descriptor.modulated(&modulation, 60, 0);
+        assert_eq!(unchanged.attack, 0.5);
+        assert_eq!(unchanged.hold, 1.0);
+        assert_eq!(unchanged.decay, 2.0);
+        assert_eq!(unchanged.release, 0.25);
+
+        // An octave up halves the hold, and the decay tracks half as much
+        let modulated = descriptor.modulated(&modulation, 72, 127);
+        assert!((modulated.hold - 0.5).abs() < 0.0001);
+        assert!((modulated.decay - 2.0 / 2.0f32.sqrt()).abs() < 0.0001);
+        assert_eq!(modulated.attack, 0.0);
+        assert_eq!(modulated.release, 1.25);
+        assert_eq!(modulated.sustain_percent, 0.5);
+    }
 }
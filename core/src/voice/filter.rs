@@ -0,0 +1,289 @@
+use std::marker::PhantomData;
+
+use simdeez::Simd;
+
+use crate::voice::VoiceControlData;
+
+use super::{SIMDSample, SIMDSampleMono, SIMDSampleStereo, SIMDVoiceGenerator, VoiceGeneratorBase};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterType {
+    LowPass,
+    HighPass,
+    BandPass,
+    Notch,
+}
+
+/// The static parameters of a voice filter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FilterDescriptor {
+    pub filter_type: FilterType,
+    pub cutoff: f32,    // Hz
+    pub resonance: f32, // Q
+}
+
+impl FilterDescriptor {
+    /// Converts a resonance peak in decibels, as used by SF2 and SFZ, into a Q value.
+    /// A resonance of 0dB results in a flat (Butterworth) response.
+    pub fn q_from_resonance_db(resonance: f32) -> f32 {
+        std::f32::consts::FRAC_1_SQRT_2 * 10.0f32.powf(resonance.max(0.0) / 20.0)
+    }
+
+    pub fn to_filter_params(&self, sample_rate: u32) -> FilterParameters {
+        FilterParameters {
+            filter_type: self.filter_type,
+            cutoff: self.cutoff,
+            resonance: self.resonance,
+            sample_rate,
+        }
+    }
+}
+
+/// The filter descriptor resolved for a sample rate
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FilterParameters {
+    pub filter_type: FilterType,
+    pub cutoff: f32,
+    pub resonance: f32,
+    pub sample_rate: u32,
+}
+
+/// Biquad filter coefficients, normalized by a0
+#[derive(Debug, Clone, Copy)]
+struct BiquadCoefficients {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl BiquadCoefficients {
+    fn new(filter_type: FilterType, cutoff: f32, q: f32, sample_rate: f32) -> Self {
+        // Keep the cutoff within the range where the filter stays stable
+        let cutoff = cutoff.max(10.0).min(sample_rate * 0.49);
+        let q = q.max(0.01);
+
+        let omega = 2.0 * std::f32::consts::PI * cutoff / sample_rate;
+        let (sin, cos) = omega.sin_cos();
+        let alpha = sin / (2.0 * q);
+
+        let (b0, b1, b2) = match filter_type {
+            FilterType::LowPass => ((1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0),
+            FilterType::HighPass => ((1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0),
+            FilterType::BandPass => (alpha, 0.0, -alpha),
+            FilterType::Notch => (1.0, -2.0 * cos, 1.0),
+        };
+        let (a0, a1, a2) = (1.0 + alpha, -2.0 * cos, 1.0 - alpha);
+
+        BiquadCoefficients {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+}
+
+/// The state of a single channel of a transposed direct form II biquad
+#[derive(Debug, Clone, Copy, Default)]
+struct BiquadState {
+    z1: f32,
+    z2: f32,
+}
+
+impl BiquadState {
+    #[inline(always)]
+    fn process(&mut self, c: &BiquadCoefficients, input: f32) -> f32 {
+        let output = c.b0 * input + self.z1;
+        self.z1 = c.b1 * input - c.a1 * output + self.z2;
+        self.z2 = c.b2 * input - c.a2 * output;
+        output
+    }
+}
+
+/// A resonant biquad filter over a mono or stereo generator.
+///
+/// The cutoff is read in Hz from another generator, which allows it to be
+/// modulated by envelopes, LFOs or controls. The coefficients are updated once
+/// per SIMD array, from its first value.
+pub struct SIMDVoiceFilter<S, TO, Gen, Cutoff>
+where
+    S: Simd,
+    TO: SIMDSample<S>,
+    Gen: SIMDVoiceGenerator<S, TO>,
+    Cutoff: SIMDVoiceGenerator<S, SIMDSampleMono<S>>,
+{
+    generator: Gen,
+    cutoff_gen: Cutoff,
+
+    filter_type: FilterType,
+    resonance: f32,
+    sample_rate: f32,
+
+    cutoff: f32,
+    coefficients: BiquadCoefficients,
+    states: [BiquadState; 2],
+
+    _s: PhantomData<S>,
+    _to: PhantomData<TO>,
+}
+
+impl<S, TO, Gen, Cutoff> SIMDVoiceFilter<S, TO, Gen, Cutoff>
+where
+    S: Simd,
+    TO: SIMDSample<S>,
+    Gen: SIMDVoiceGenerator<S, TO>,
+    Cutoff: SIMDVoiceGenerator<S, SIMDSampleMono<S>>,
+{
+    /// Creates a filter over `generator`, with the cutoff read from `cutoff_gen`
+    /// rather than the parameters, so that it can be modulated.
+    pub fn new(params: &FilterParameters, generator: Gen, cutoff_gen: Cutoff) -> Self {
+        let FilterParameters {
+            filter_type,
+            resonance,
+            ..
+        } = *params;
+        let sample_rate = params.sample_rate as f32;
+        let cutoff = sample_rate / 2.0;
+
+        SIMDVoiceFilter {
+            generator,
+            cutoff_gen,
+            filter_type,
+            resonance,
+            sample_rate,
+            cutoff,
+            coefficients: BiquadCoefficients::new(filter_type, cutoff, resonance, sample_rate),
+            states: [BiquadState::default(); 2],
+            _s: PhantomData,
+            _to: PhantomData,
+        }
+    }
+
+    fn update_cutoff(&mut self) {
+        let cutoff = self.cutoff_gen.next_sample().0[0];
+        if cutoff != self.cutoff {
+            self.cutoff = cutoff;
+            self.coefficients =
+                BiquadCoefficients::new(self.filter_type, cutoff, self.resonance, self.sample_rate);
+        }
+    }
+
+    #[inline(always)]
+    fn filter_channel(&mut self, channel: usize, values: &mut S::Vf32) {
+        let state = &mut self.states[channel];
+        for i in 0..S::VF32_WIDTH {
+            values[i] = state.process(&self.coefficients, values[i]);
+        }
+    }
+}
+
+impl<S, TO, Gen, Cutoff> VoiceGeneratorBase for SIMDVoiceFilter<S, TO, Gen, Cutoff>
+where
+    S: Simd,
+    TO: SIMDSample<S>,
+    Gen: SIMDVoiceGenerator<S, TO>,
+    Cutoff: SIMDVoiceGenerator<S, SIMDSampleMono<S>>,
+{
+    fn ended(&self) -> bool {
+        self.generator.ended()
+    }
+
+    fn signal_release(&mut self) {
+        self.generator.signal_release();
+        self.cutoff_gen.signal_release();
+    }
+
+    fn process_controls(&mut self, control: &VoiceControlData) {
+        self.generator.process_controls(control);
+        self.cutoff_gen.process_controls(control);
+    }
+
+    fn envelope_level(&self) -> f32 {
+        self.generator.envelope_level()
+    }
+}
+
+impl<S, Gen, Cutoff> SIMDVoiceGenerator<S, SIMDSampleMono<S>>
+    for SIMDVoiceFilter<S, SIMDSampleMono<S>, Gen, Cutoff>
+where
+    S: Simd,
+    Gen: SIMDVoiceGenerator<S, SIMDSampleMono<S>>,
+    Cutoff: SIMDVoiceGenerator<S, SIMDSampleMono<S>>,
+{
+    fn next_sample(&mut self) -> SIMDSampleMono<S> {
+        self.update_cutoff();
+
+        let mut sample = self.generator.next_sample();
+        self.filter_channel(0, &mut sample.0);
+        sample
+    }
+}
+
+impl<S, Gen, Cutoff> SIMDVoiceGenerator<S, SIMDSampleStereo<S>>
+    for SIMDVoiceFilter<S, SIMDSampleStereo<S>, Gen, Cutoff>
+where
+    S: Simd,
+    Gen: SIMDVoiceGenerator<S, SIMDSampleStereo<S>>,
+    Cutoff: SIMDVoiceGenerator<S, SIMDSampleMono<S>>,
+{
+    fn next_sample(&mut self) -> SIMDSampleStereo<S> {
+        self.update_cutoff();
+
+        let mut sample = self.generator.next_sample();
+        self.filter_channel(0, &mut sample.0);
+        self.filter_channel(1, &mut sample.1);
+        sample
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voice::SIMDConstant;
+
+    use simdeez::*; // nuts
+
+    use simdeez::avx2::*;
+    use simdeez::scalar::*;
+    use simdeez::sse2::*;
+    use simdeez::sse41::*;
+
+    #[test]
+    fn test_filter_dc_response() {
+        simd_runtime_generate!(
+            fn run() {
+                let settled_value = |filter_type| {
+                    let params = FilterDescriptor {
+                        filter_type,
+                        cutoff: 1000.0,
+                        resonance: FilterDescriptor::q_from_resonance_db(0.0),
+                    }
+                    .to_filter_params(48000);
+
+                    let mut filter = SIMDVoiceFilter::new(
+                        &params,
+                        SIMDConstant::<S>::new(1.0),
+                        SIMDConstant::<S>::new(params.cutoff),
+                    );
+
+                    let mut sample = SIMDSampleMono::<S>::zero();
+                    for _ in 0..(48000 / S::VF32_WIDTH) {
+                        sample = filter.next_sample();
+                    }
+                    sample.0[S::VF32_WIDTH - 1]
+                };
+
+                // A constant signal is only passed through by the low-pass and notch filters
+                assert!((settled_value(FilterType::LowPass) - 1.0).abs() < 0.001);
+                assert!(settled_value(FilterType::HighPass).abs() < 0.001);
+                assert!(settled_value(FilterType::BandPass).abs() < 0.001);
+                assert!((settled_value(FilterType::Notch) - 1.0).abs() < 0.001);
+            }
+        );
+
+        run_runtime_select();
+    }
+}
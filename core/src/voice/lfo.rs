@@ -0,0 +1,223 @@
+use std::marker::PhantomData;
+
+use simdeez::Simd;
+
+use crate::voice::VoiceControlData;
+
+use super::{SIMDSampleMono, SIMDVoiceGenerator, VoiceGeneratorBase};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LfoShape {
+    Sine,
+    Triangle,
+    Square,
+    Saw,
+    /// A random value, held for each cycle
+    SampleAndHold,
+}
+
+/// The original LFO descriptor
+#[derive(Debug, Copy, Clone)]
+pub struct LfoDescriptor {
+    pub shape: LfoShape,
+    pub frequency: f32, // Hz
+    pub delay: f32,     // Seconds
+    pub fade: f32,      // Seconds
+}
+
+impl LfoDescriptor {
+    pub fn to_lfo_params(&self, samplerate: u32) -> LfoParameters {
+        let samplerate = samplerate as f32;
+
+        LfoParameters {
+            shape: self.shape,
+            step: self.frequency.max(0.0) / samplerate,
+            delay: (self.delay.max(0.0) * samplerate) as u32,
+            fade: (self.fade.max(0.0) * samplerate) as u32,
+        }
+    }
+}
+
+/// The LFO descriptor converted into samples
+#[derive(Debug, Copy, Clone)]
+pub struct LfoParameters {
+    shape: LfoShape,
+    step: f32,  // Cycles per sample
+    delay: u32, // Samples
+    fade: u32,  // Samples
+}
+
+/// A low frequency oscillator, outputting values between -1 and 1.
+///
+/// The output stays at 0 during the delay, then fades in linearly. It can be
+/// combined with constants and controls through `VoiceCombineSIMD` to set its
+/// depth, for example to build vibrato, tremolo or filter modulation.
+pub struct SIMDVoiceLFO<S: Simd> {
+    params: LfoParameters,
+    time: u32,
+    phase: f32,
+    held_value: f32,
+    random_state: u32,
+    _s: PhantomData<S>,
+}
+
+impl<S: Simd> SIMDVoiceLFO<S> {
+    pub fn new(params: &LfoParameters) -> Self {
+        let mut lfo = SIMDVoiceLFO {
+            params: *params,
+            time: 0,
+            phase: 0.0,
+            held_value: 0.0,
+            random_state: 0x9E3779B9,
+            _s: PhantomData,
+        };
+        lfo.held_value = lfo.next_random();
+        lfo
+    }
+
+    /// A xorshift random value between -1 and 1, for the sample and hold shape
+    fn next_random(&mut self) -> f32 {
+        let mut x = self.random_state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random_state = x;
+        (x as f32 / u32::MAX as f32) * 2.0 - 1.0
+    }
+
+    fn shape_value(&self) -> f32 {
+        let phase = self.phase;
+        match self.params.shape {
+            LfoShape::Sine => (phase * 2.0 * std::f32::consts::PI).sin(),
+            LfoShape::Triangle => 4.0 * ((phase + 0.75) % 1.0 - 0.5).abs() - 1.0,
+            LfoShape::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            LfoShape::Saw => 2.0 * ((phase + 0.5) % 1.0) - 1.0,
+            LfoShape::SampleAndHold => self.held_value,
+        }
+    }
+
+    fn next_value(&mut self) -> f32 {
+        let params = self.params;
+        if self.time < params.delay {
+            self.time += 1;
+            return 0.0;
+        }
+
+        let faded = self.time - params.delay;
+        let fade = if faded < params.fade {
+            faded as f32 / params.fade as f32
+        } else {
+            1.0
+        };
+
+        let value = self.shape_value() * fade;
+
+        self.time = self.time.saturating_add(1);
+        self.phase += params.step;
+        if self.phase >= 1.0 {
+            self.phase %= 1.0;
+            if params.shape == LfoShape::SampleAndHold {
+                self.held_value = self.next_random();
+            }
+        }
+
+        value
+    }
+}
+
+impl<S: Simd> VoiceGeneratorBase for SIMDVoiceLFO<S> {
+    fn ended(&self) -> bool {
+        false
+    }
+
+    fn signal_release(&mut self) {}
+
+    fn process_controls(&mut self, _control: &VoiceControlData) {}
+}
+
+impl<S: Simd> SIMDVoiceGenerator<S, SIMDSampleMono<S>> for SIMDVoiceLFO<S> {
+    fn next_sample(&mut self) -> SIMDSampleMono<S> {
+        let mut values = unsafe { S::set1_ps(0.0) };
+        for i in 0..S::VF32_WIDTH {
+            values[i] = self.next_value();
+        }
+        SIMDSampleMono(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use simdeez::*; // nuts
+
+    use simdeez::avx2::*;
+    use simdeez::scalar::*;
+    use simdeez::sse2::*;
+    use simdeez::sse41::*;
+
+    #[test]
+    fn test_lfo() {
+        simd_runtime_generate!(
+            fn run() {
+                let render = |shape, delay, fade| {
+                    let params = LfoDescriptor {
+                        shape,
+                        frequency: 1000.0,
+                        delay,
+                        fade,
+                    }
+                    .to_lfo_params(64000);
+
+                    let mut lfo = SIMDVoiceLFO::<S>::new(&params);
+                    let mut values = Vec::new();
+                    for _ in 0..(256 / S::VF32_WIDTH) {
+                        let sample = lfo.next_sample();
+                        for i in 0..S::VF32_WIDTH {
+                            values.push(sample.0[i]);
+                        }
+                    }
+                    values
+                };
+
+                // One cycle is 64 samples, so the peaks are 16 samples in
+                let sine = render(LfoShape::Sine, 0.0, 0.0);
+                assert!(sine[0].abs() < 0.0001);
+                assert!((sine[16] - 1.0).abs() < 0.0001);
+                assert!((sine[48] + 1.0).abs() < 0.0001);
+
+                let triangle = render(LfoShape::Triangle, 0.0, 0.0);
+                assert!(triangle[0].abs() < 0.0001);
+                assert!((triangle[16] - 1.0).abs() < 0.0001);
+                assert!((triangle[48] + 1.0).abs() < 0.0001);
+
+                let square = render(LfoShape::Square, 0.0, 0.0);
+                assert_eq!(square[0], 1.0);
+                assert_eq!(square[40], -1.0);
+
+                let saw = render(LfoShape::Saw, 0.0, 0.0);
+                assert!(saw[0].abs() < 0.0001);
+                assert!(saw[31] > saw[0]);
+
+                // Sample and hold keeps its value for a whole cycle
+                let held = render(LfoShape::SampleAndHold, 0.0, 0.0);
+                assert!(held[0..64].iter().all(|v| *v == held[0]));
+                assert!(held.iter().all(|v| v.abs() <= 1.0));
+
+                // The delay (64 samples) outputs nothing, then the fade (64 samples) ramps up
+                let delayed = render(LfoShape::Square, 0.001, 0.001);
+                assert!(delayed[0..64].iter().all(|v| *v == 0.0));
+                assert!((delayed[80] - 0.25).abs() < 0.0001);
+                assert_eq!(delayed[128], 1.0);
+            }
+        );
+
+        run_runtime_select();
+    }
+}
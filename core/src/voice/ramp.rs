@@ -0,0 +1,153 @@
+use std::marker::PhantomData;
+
+use simdeez::Simd;
+
+use crate::voice::VoiceControlData;
+
+use super::{SIMDSampleMono, SIMDVoiceGenerator, VoiceGeneratorBase};
+
+/// A value read from the control data, which moves towards each new value at
+/// a fixed rate instead of jumping to it, such as the pitch of a portamento.
+///
+/// The update function returns the target value, along with the maximum change
+/// per sample. A rate that isn't finite and positive jumps to the target.
+pub struct SIMDVoiceRamp<S: Simd> {
+    value: f32,
+    target: f32,
+    step: f32, // Change per sample
+    update: fn(&VoiceControlData) -> (f32, f32),
+    _s: PhantomData<S>,
+}
+
+impl<S: Simd> SIMDVoiceRamp<S> {
+    /// Creates a ramp starting at `start`, moving towards the target read from `control`
+    pub fn new(
+        start: f32,
+        control: &VoiceControlData,
+        update: fn(&VoiceControlData) -> (f32, f32),
+    ) -> Self {
+        let mut ramp = SIMDVoiceRamp {
+            value: start,
+            target: start,
+            step: 0.0,
+            update,
+            _s: PhantomData,
+        };
+        let (target, rate) = (update)(control);
+        ramp.set_target(target, rate);
+        ramp
+    }
+
+    fn set_target(&mut self, target: f32, rate: f32) {
+        self.target = target;
+
+        let distance = target - self.value;
+        if distance == 0.0 || !rate.is_finite() || rate <= 0.0 {
+            self.value = target;
+            self.step = 0.0;
+        } else {
+            self.step = rate.copysign(distance);
+        }
+    }
+
+    fn next_value(&mut self) -> f32 {
+        let value = self.value;
+        self.value += self.step;
+
+        let reached = (self.step > 0.0 && self.value >= self.target)
+            || (self.step < 0.0 && self.value <= self.target);
+        if reached {
+            self.value = self.target;
+            self.step = 0.0;
+        }
+
+        value
+    }
+}
+
+impl<S: Simd> VoiceGeneratorBase for SIMDVoiceRamp<S> {
+    fn ended(&self) -> bool {
+        false
+    }
+
+    fn signal_release(&mut self) {}
+
+    fn process_controls(&mut self, control: &VoiceControlData) {
+        // Other control changes shouldn't restart a ramp that is in progress
+        let (target, rate) = (self.update)(control);
+        if target != self.target {
+            self.set_target(target, rate);
+        }
+    }
+}
+
+impl<S: Simd> SIMDVoiceGenerator<S, SIMDSampleMono<S>> for SIMDVoiceRamp<S> {
+    fn next_sample(&mut self) -> SIMDSampleMono<S> {
+        let mut values = unsafe { S::set1_ps(self.value) };
+        if self.step != 0.0 {
+            for i in 0..S::VF32_WIDTH {
+                values[i] = self.next_value();
+            }
+        }
+        SIMDSampleMono(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use simdeez::*; // nuts
+
+    use simdeez::avx2::*;
+    use simdeez::scalar::*;
+    use simdeez::sse2::*;
+    use simdeez::sse41::*;
+
+    #[test]
+    fn test_ramp() {
+        simd_runtime_generate!(
+            fn run() {
+                let render = |ramp: &mut SIMDVoiceRamp<S>, count: usize| {
+                    let mut values = Vec::new();
+                    while values.len() < count {
+                        let sample = ramp.next_sample();
+                        for i in 0..S::VF32_WIDTH {
+                            values.push(sample.0[i]);
+                        }
+                    }
+                    values
+                };
+
+                let mut control = VoiceControlData::new_defaults();
+                control.pitch_offset = 2.0;
+                control.portamento_rate = 0.25;
+                let update = |vc: &VoiceControlData| (vc.pitch_offset, vc.portamento_rate);
+
+                // Moves by the rate each sample, then stays at the target
+                let mut ramp = SIMDVoiceRamp::<S>::new(0.0, &control, update);
+                let values = render(&mut ramp, 16);
+                assert_eq!(values[0], 0.0);
+                assert_eq!(values[4], 1.0);
+                assert!(values[8..].iter().all(|v| *v == 2.0));
+
+                // Moves back down from the current value when the target changes
+                control.pitch_offset = 1.0;
+                ramp.process_controls(&control);
+                let values = render(&mut ramp, 16);
+                assert_eq!(values[0], 2.0);
+                assert_eq!(values[2], 1.5);
+                assert!(values[4..].iter().all(|v| *v == 1.0));
+
+                // Jumps to the target without a rate
+                control.pitch_offset = -3.0;
+                control.portamento_rate = f32::INFINITY;
+                ramp.process_controls(&control);
+                let values = render(&mut ramp, 8);
+                assert!(values.iter().all(|v| *v == -3.0));
+            }
+        );
+
+        run_runtime_select();
+    }
+}
@@ -6,12 +6,18 @@ use crate::voice::VoiceControlData;
 
 use super::{SIMDSampleMono, SIMDSampleStereo, SIMDVoiceGenerator, VoiceGeneratorBase};
 
+mod cubic;
+pub use cubic::*;
+
 mod linear;
 pub use linear::*;
 
 mod nearest;
 pub use nearest::*;
 
+mod sinc;
+pub use sinc::*;
+
 // I believe some terminology reference is relevant for this one.
 //
 // BufferSampler: Something that grabs a sample based on an index
@@ -36,6 +42,12 @@ pub trait SIMDSampleGrabber<S: Simd>: Send + Sync {
     fn get(&self, indexes: S::Vi32, fractional: S::Vf32) -> S::Vf32;
 
     fn is_past_end(&self, pos: f64) -> bool;
+
+    /// Wraps a playback position back into the sample's loop, if it is looping
+    fn wrap_position(&self, pos: f64) -> f64;
+
+    /// Stops any sustain loops
+    fn signal_release(&mut self);
 }
 
 // F32 sampler
@@ -86,23 +98,162 @@ impl BufferSampler for BufferSamplers {
 
 // Enum sampler reader
 
+/// How a sample behaves once playback reaches its loop end
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopMode {
+    /// Play the sample until its end, stopping early if the voice is released
+    NoLoop,
+
+    /// Play the sample until its end, ignoring releases
+    OneShot,
+
+    /// Loop between the loop points forever, including after release
+    LoopContinuous,
+
+    /// Loop between the loop points until released, then play until the end
+    LoopSustain,
+}
+
+/// The playback region and loop points of a sample. All positions are indexes
+/// into the sample buffer, with the ends being exclusive.
+#[derive(Debug, Clone)]
+pub struct SampleReaderParams {
+    pub offset: usize,
+    pub end: Option<usize>,
+    pub loop_mode: LoopMode,
+    pub loop_start: usize,
+    pub loop_end: usize,
+}
+
+impl Default for SampleReaderParams {
+    fn default() -> Self {
+        SampleReaderParams {
+            offset: 0,
+            end: None,
+            loop_mode: LoopMode::NoLoop,
+            loop_start: 0,
+            loop_end: 0,
+        }
+    }
+}
+
+impl SampleReaderParams {
+    /// Scales the positions to match a sample that was resampled from one
+    /// sample rate to another
+    pub fn resampled(&self, from_rate: u32, to_rate: u32) -> SampleReaderParams {
+        let scale = |pos: usize| (pos as f64 * to_rate as f64 / from_rate as f64) as usize;
+
+        SampleReaderParams {
+            offset: scale(self.offset),
+            end: self.end.map(scale),
+            loop_mode: self.loop_mode,
+            loop_start: scale(self.loop_start),
+            loop_end: scale(self.loop_end),
+        }
+    }
+}
+
 pub struct SampleReader<Sampler: BufferSampler> {
     buffer: Sampler,
+    offset: usize,
     length: Option<usize>,
-    // TODO: add start/end/loop points
+    loop_mode: LoopMode,
+
+    // Relative to the offset
+    loop_start: usize,
+    loop_end: usize,
+
+    released: bool,
 }
 
 impl<Sampler: BufferSampler> SampleReader<Sampler> {
     pub fn new(buffer: Sampler) -> Self {
-        let length = Some(buffer.length());
-        SampleReader { buffer, length }
+        SampleReader::new_with_params(buffer, &SampleReaderParams::default())
+    }
+
+    pub fn new_with_params(buffer: Sampler, params: &SampleReaderParams) -> Self {
+        let buffer_len = buffer.length();
+        let end = params.end.unwrap_or(buffer_len).min(buffer_len);
+        let offset = params.offset.min(end);
+
+        let loop_start = params.loop_start.max(offset).min(end) - offset;
+        let loop_end = params.loop_end.max(offset).min(end) - offset;
+
+        // A loop with no length can't be played, so the sample is treated as not looping
+        let loop_mode = match params.loop_mode {
+            LoopMode::LoopContinuous | LoopMode::LoopSustain if loop_end <= loop_start => {
+                LoopMode::NoLoop
+            }
+            mode => mode,
+        };
+
+        SampleReader {
+            buffer,
+            offset,
+            length: Some(end - offset),
+            loop_mode,
+            loop_start,
+            loop_end,
+            released: false,
+        }
     }
 
     pub fn get(&self, pos: usize) -> f32 {
-        self.buffer.get(pos)
+        let pos = if self.is_looping() && pos >= self.loop_end {
+            self.loop_start + (pos - self.loop_start) % (self.loop_end - self.loop_start)
+        } else {
+            pos
+        };
+
+        if self.is_past_end(pos) {
+            0.0
+        } else {
+            self.buffer.get(pos + self.offset)
+        }
+    }
+
+    /// Gets the sample at an offset from a position, for interpolators which read
+    /// the samples around it. Positions before the start of the sample are silent.
+    pub fn get_relative(&self, pos: usize, offset: isize) -> f32 {
+        if offset < 0 && pos < offset.unsigned_abs() {
+            0.0
+        } else {
+            self.get((pos as isize + offset) as usize)
+        }
+    }
+
+    pub fn loop_mode(&self) -> LoopMode {
+        self.loop_mode
+    }
+
+    fn is_looping(&self) -> bool {
+        match self.loop_mode {
+            LoopMode::LoopContinuous => true,
+            LoopMode::LoopSustain => !self.released,
+            LoopMode::NoLoop | LoopMode::OneShot => false,
+        }
+    }
+
+    /// Moves a playback position past the loop end back into the loop
+    fn wrap_position(&self, pos: f64) -> f64 {
+        let loop_end = self.loop_end as f64;
+        if self.is_looping() && pos >= loop_end {
+            let loop_start = self.loop_start as f64;
+            loop_start + (pos - loop_start) % (loop_end - loop_start)
+        } else {
+            pos
+        }
+    }
+
+    fn signal_release(&mut self) {
+        self.released = true;
     }
 
     fn is_past_end(&self, pos: usize) -> bool {
+        if self.is_looping() {
+            return false;
+        }
+
         if let Some(len) = self.length {
             pos >= len
         } else {
@@ -116,6 +267,8 @@ impl<Sampler: BufferSampler> SampleReader<Sampler> {
 pub enum SIMDSampleGrabbers<S: Simd, Sampler: BufferSampler> {
     Nearest(SIMDNearestSampleGrabber<S, Sampler>),
     Linear(SIMDLinearSampleGrabber<S, Sampler>),
+    Cubic(SIMDCubicSampleGrabber<S, Sampler>),
+    Sinc(SIMDSincSampleGrabber<S, Sampler>),
 }
 
 impl<S: Simd, Sampler: BufferSampler> SIMDSampleGrabbers<S, Sampler> {
@@ -126,6 +279,14 @@ impl<S: Simd, Sampler: BufferSampler> SIMDSampleGrabbers<S, Sampler> {
     pub fn linear(reader: SampleReader<Sampler>) -> Self {
         SIMDSampleGrabbers::Linear(SIMDLinearSampleGrabber::new(reader))
     }
+
+    pub fn cubic(reader: SampleReader<Sampler>) -> Self {
+        SIMDSampleGrabbers::Cubic(SIMDCubicSampleGrabber::new(reader))
+    }
+
+    pub fn sinc(reader: SampleReader<Sampler>, taps: SincTaps) -> Self {
+        SIMDSampleGrabbers::Sinc(SIMDSincSampleGrabber::new(reader, taps))
+    }
 }
 
 impl<S: Simd, Sampler: BufferSampler> SIMDSampleGrabber<S> for SIMDSampleGrabbers<S, Sampler> {
@@ -134,6 +295,8 @@ impl<S: Simd, Sampler: BufferSampler> SIMDSampleGrabber<S> for SIMDSampleGrabber
         match self {
             SIMDSampleGrabbers::Linear(grabber) => grabber.get(indexes, fractional),
             SIMDSampleGrabbers::Nearest(grabber) => grabber.get(indexes, fractional),
+            SIMDSampleGrabbers::Cubic(grabber) => grabber.get(indexes, fractional),
+            SIMDSampleGrabbers::Sinc(grabber) => grabber.get(indexes, fractional),
         }
     }
 
@@ -142,6 +305,27 @@ impl<S: Simd, Sampler: BufferSampler> SIMDSampleGrabber<S> for SIMDSampleGrabber
         match self {
             SIMDSampleGrabbers::Linear(grabber) => grabber.is_past_end(pos),
             SIMDSampleGrabbers::Nearest(grabber) => grabber.is_past_end(pos),
+            SIMDSampleGrabbers::Cubic(grabber) => grabber.is_past_end(pos),
+            SIMDSampleGrabbers::Sinc(grabber) => grabber.is_past_end(pos),
+        }
+    }
+
+    #[inline(always)]
+    fn wrap_position(&self, pos: f64) -> f64 {
+        match self {
+            SIMDSampleGrabbers::Linear(grabber) => grabber.wrap_position(pos),
+            SIMDSampleGrabbers::Nearest(grabber) => grabber.wrap_position(pos),
+            SIMDSampleGrabbers::Cubic(grabber) => grabber.wrap_position(pos),
+            SIMDSampleGrabbers::Sinc(grabber) => grabber.wrap_position(pos),
+        }
+    }
+
+    fn signal_release(&mut self) {
+        match self {
+            SIMDSampleGrabbers::Linear(grabber) => grabber.signal_release(),
+            SIMDSampleGrabbers::Nearest(grabber) => grabber.signal_release(),
+            SIMDSampleGrabbers::Cubic(grabber) => grabber.signal_release(),
+            SIMDSampleGrabbers::Sinc(grabber) => grabber.signal_release(),
         }
     }
 }
@@ -180,9 +364,11 @@ where
         }
     }
 
+    /// Advances the playback position, wrapping it within the sample loop.
+    /// Both channels share the same loop points, so only the left grabber is checked.
     fn increment_time(&mut self, by: f64) -> f64 {
         let time = self.time;
-        self.time += by;
+        self.time = self.grabber_left.wrap_position(self.time + by);
         time
     }
 }
@@ -198,6 +384,8 @@ where
     }
 
     fn signal_release(&mut self) {
+        self.grabber_left.signal_release();
+        self.grabber_right.signal_release();
         self.pitch_gen.signal_release();
     }
 
@@ -230,3 +418,138 @@ where
         SIMDSampleStereo(left, right)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use simdeez::*; // nuts
+
+    use simdeez::avx2::*;
+    use simdeez::scalar::*;
+    use simdeez::sse2::*;
+    use simdeez::sse41::*;
+
+    use crate::voice::SIMDConstant;
+
+    #[test]
+    fn test_sample_loops() {
+        simd_runtime_generate!(
+            fn run() {
+                let sample: Arc<[f32]> = (0..10).map(|i| i as f32).collect::<Vec<_>>().into();
+
+                let params = SampleReaderParams {
+                    offset: 1,
+                    end: Some(9),
+                    loop_mode: LoopMode::LoopSustain,
+                    loop_start: 3,
+                    loop_end: 6,
+                };
+
+                let grabber = || {
+                    let reader = SampleReader::new_with_params(
+                        BufferSamplers::new_f32(sample.clone()),
+                        &params,
+                    );
+                    SIMDNearestSampleGrabber::<S, _>::new(reader)
+                };
+
+                let mut sampler =
+                    SIMDStereoVoiceSampler::new(grabber(), grabber(), SIMDConstant::<S>::new(1.0));
+
+                // The loop wraps within each SIMD batch, and keeps looping until released
+                let expected_loop = [1.0, 2.0, 3.0, 4.0, 5.0, 3.0, 4.0, 5.0, 3.0, 4.0];
+                let mut output = Vec::new();
+                for _ in 0..(100 / S::VF32_WIDTH) {
+                    let sample = sampler.next_sample();
+                    for i in 0..S::VF32_WIDTH {
+                        assert_eq!(sample.0[i], sample.1[i]);
+                        output.push(sample.0[i]);
+                    }
+                    assert!(!sampler.ended());
+                }
+                assert_eq!(&output[..expected_loop.len()], &expected_loop);
+                for (i, &sample) in output.iter().enumerate().skip(2) {
+                    assert_eq!(sample, 3.0 + ((i - 2) % 3) as f32);
+                }
+
+                // After releasing, the sample plays through to its end
+                sampler.signal_release();
+                let mut released = Vec::new();
+                while !sampler.ended() {
+                    let sample = sampler.next_sample();
+                    for i in 0..S::VF32_WIDTH {
+                        released.push(sample.0[i]);
+                    }
+                }
+                let last = *output.last().unwrap();
+                let mut expected = last + 1.0;
+                for value in released.iter() {
+                    if expected <= 8.0 {
+                        assert_eq!(*value, expected);
+                    } else {
+                        assert_eq!(*value, 0.0);
+                    }
+                    expected += 1.0;
+                }
+            }
+        );
+
+        run_runtime_select();
+    }
+
+    #[test]
+    fn test_interpolation() {
+        simd_runtime_generate!(
+            fn run() {
+                let sample: Arc<[f32]> = (0..64).map(|i| i as f32).collect::<Vec<_>>().into();
+                let grabbers = || {
+                    let reader = || SampleReader::new(BufferSamplers::new_f32(sample.clone()));
+                    vec![
+                        SIMDSampleGrabbers::<S, _>::linear(reader()),
+                        SIMDSampleGrabbers::cubic(reader()),
+                        SIMDSampleGrabbers::sinc(reader(), SincTaps::Taps8),
+                        SIMDSampleGrabbers::sinc(reader(), SincTaps::Taps16),
+                        SIMDSampleGrabbers::sinc(reader(), SincTaps::Taps32),
+                    ]
+                };
+
+                // Every interpolator reproduces a ramp away from the sample edges
+                for grabber in grabbers() {
+                    for fractional in [0.0, 0.25, 0.5, 0.75].iter() {
+                        let indexes = S::set1_epi32(30);
+                        let values = grabber.get(indexes, S::set1_ps(*fractional));
+                        for i in 0..S::VF32_WIDTH {
+                            assert!((values[i] - (30.0 + fractional)).abs() < 0.01);
+                        }
+                    }
+                }
+            }
+        );
+
+        run_runtime_select();
+    }
+
+    #[test]
+    fn test_linear_interpolation() {
+        simd_runtime_generate!(
+            fn run() {
+                let sample: Arc<[f32]> = vec![0.0, 1.0, 0.0, 0.0].into();
+                let reader = SampleReader::new(BufferSamplers::new_f32(sample));
+                let grabber = SIMDLinearSampleGrabber::<S, _>::new(reader);
+
+                // The blend moves from the sample at the index towards the next one
+                let values = grabber.get(S::set1_epi32(0), S::set1_ps(0.25));
+                for i in 0..S::VF32_WIDTH {
+                    assert_eq!(values[i], 0.25);
+                }
+                let values = grabber.get(S::set1_epi32(1), S::set1_ps(0.25));
+                for i in 0..S::VF32_WIDTH {
+                    assert_eq!(values[i], 0.75);
+                }
+            }
+        );
+
+        run_runtime_select();
+    }
+}
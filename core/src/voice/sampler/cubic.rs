@@ -0,0 +1,59 @@
+use std::marker::PhantomData;
+
+use simdeez::Simd;
+
+use super::{BufferSampler, SIMDSampleGrabber, SampleReader};
+
+/// Interpolates between samples with a Catmull-Rom (cubic Hermite) spline,
+/// through the two samples on either side of the position.
+pub struct SIMDCubicSampleGrabber<S: Simd, Sampler: BufferSampler> {
+    sampler_reader: SampleReader<Sampler>,
+    _s: PhantomData<S>,
+}
+
+impl<S: Simd, Sampler: BufferSampler> SIMDCubicSampleGrabber<S, Sampler> {
+    pub fn new(sampler_reader: SampleReader<Sampler>) -> Self {
+        SIMDCubicSampleGrabber {
+            sampler_reader,
+            _s: PhantomData,
+        }
+    }
+}
+
+impl<S: Simd, Sampler: BufferSampler> SIMDSampleGrabber<S> for SIMDCubicSampleGrabber<S, Sampler> {
+    fn get(&self, indexes: S::Vi32, fractional: S::Vf32) -> S::Vf32 {
+        let zeros = unsafe { S::set1_ps(0.0) };
+        let halves = unsafe { S::set1_ps(0.5) };
+        let mut values = [zeros; 4];
+
+        for i in 0..S::VF32_WIDTH {
+            let index = indexes[i] as usize;
+            for (offset, value) in values.iter_mut().enumerate() {
+                value[i] = self.sampler_reader.get_relative(index, offset as isize - 1);
+            }
+        }
+
+        let [p0, p1, p2, p3] = values;
+        let t = fractional;
+
+        let c1 = (p2 - p0) * halves;
+        let c2 =
+            p0 - p1 * unsafe { S::set1_ps(2.5) } + p2 * unsafe { S::set1_ps(2.0) } - p3 * halves;
+        let c3 = (p3 - p0) * halves + (p1 - p2) * unsafe { S::set1_ps(1.5) };
+
+        ((c3 * t + c2) * t + c1) * t + p1
+    }
+
+    fn is_past_end(&self, pos: f64) -> bool {
+        let pos = pos as usize;
+        self.sampler_reader.is_past_end(pos)
+    }
+
+    fn wrap_position(&self, pos: f64) -> f64 {
+        self.sampler_reader.wrap_position(pos)
+    }
+
+    fn signal_release(&mut self) {
+        self.sampler_reader.signal_release();
+    }
+}
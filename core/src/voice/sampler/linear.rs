@@ -31,7 +31,7 @@ impl<S: Simd, Sampler: BufferSampler> SIMDSampleGrabber<S> for SIMDLinearSampleG
             values_second[i] = self.sampler_reader.get(index + 1);
         }
 
-        let blended = values_first * blend + values_second * (ones - blend);
+        let blended = values_first * (ones - blend) + values_second * blend;
 
         blended
     }
@@ -40,4 +40,12 @@ impl<S: Simd, Sampler: BufferSampler> SIMDSampleGrabber<S> for SIMDLinearSampleG
         let pos = pos as usize;
         self.sampler_reader.is_past_end(pos)
     }
+
+    fn wrap_position(&self, pos: f64) -> f64 {
+        self.sampler_reader.wrap_position(pos)
+    }
+
+    fn signal_release(&mut self) {
+        self.sampler_reader.signal_release();
+    }
 }
@@ -37,4 +37,12 @@ impl<S: Simd, Sampler: BufferSampler> SIMDSampleGrabber<S>
         let pos = pos as usize;
         self.sampler_reader.is_past_end(pos)
     }
+
+    fn wrap_position(&self, pos: f64) -> f64 {
+        self.sampler_reader.wrap_position(pos)
+    }
+
+    fn signal_release(&mut self) {
+        self.sampler_reader.signal_release();
+    }
 }
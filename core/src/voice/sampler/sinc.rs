@@ -0,0 +1,146 @@
+use std::{f32::consts::PI, marker::PhantomData};
+
+use lazy_static::lazy_static;
+use simdeez::Simd;
+
+use super::{BufferSampler, SIMDSampleGrabber, SampleReader};
+
+/// The number of fractional positions the sinc kernels are tabulated at
+const SINC_PHASES: usize = 256;
+
+/// The length of a sinc interpolation kernel, in samples. Longer kernels
+/// alias less, but read more samples per output sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SincTaps {
+    Taps8,
+    Taps16,
+    Taps32,
+}
+
+impl SincTaps {
+    pub fn count(&self) -> usize {
+        match self {
+            SincTaps::Taps8 => 8,
+            SincTaps::Taps16 => 16,
+            SincTaps::Taps32 => 32,
+        }
+    }
+
+    fn table(&self) -> &'static SincTable {
+        match self {
+            SincTaps::Taps8 => &SINC_TABLE_8,
+            SincTaps::Taps16 => &SINC_TABLE_16,
+            SincTaps::Taps32 => &SINC_TABLE_32,
+        }
+    }
+}
+
+/// Blackman windowed sinc kernels, for each fractional position
+struct SincTable {
+    taps: usize,
+    coefficients: Box<[f32]>, // SINC_PHASES * taps, grouped by phase
+}
+
+impl SincTable {
+    fn new(taps: usize) -> Self {
+        let mut coefficients = Vec::with_capacity(SINC_PHASES * taps);
+        let half = taps as f32 / 2.0;
+
+        for phase in 0..SINC_PHASES {
+            let fractional = phase as f32 / SINC_PHASES as f32;
+            let kernel: Vec<f32> = (0..taps)
+                .map(|tap| {
+                    let x = tap as f32 - (half - 1.0) - fractional;
+                    let sinc = if x == 0.0 {
+                        1.0
+                    } else {
+                        (PI * x).sin() / (PI * x)
+                    };
+                    let window =
+                        0.42 + 0.5 * (PI * x / half).cos() + 0.08 * (2.0 * PI * x / half).cos();
+                    sinc * window
+                })
+                .collect();
+
+            // Normalize each kernel so that constant signals keep their level
+            let sum: f32 = kernel.iter().sum();
+            coefficients.extend(kernel.iter().map(|c| c / sum));
+        }
+
+        SincTable {
+            taps,
+            coefficients: coefficients.into(),
+        }
+    }
+
+    #[inline(always)]
+    fn coefficient(&self, phase: usize, tap: usize) -> f32 {
+        self.coefficients[phase * self.taps + tap]
+    }
+}
+
+lazy_static! {
+    static ref SINC_TABLE_8: SincTable = SincTable::new(8);
+    static ref SINC_TABLE_16: SincTable = SincTable::new(16);
+    static ref SINC_TABLE_32: SincTable = SincTable::new(32);
+}
+
+/// Interpolates between samples with a windowed sinc kernel, read from a table
+/// shared between all grabbers with the same number of taps.
+pub struct SIMDSincSampleGrabber<S: Simd, Sampler: BufferSampler> {
+    sampler_reader: SampleReader<Sampler>,
+    table: &'static SincTable,
+    _s: PhantomData<S>,
+}
+
+impl<S: Simd, Sampler: BufferSampler> SIMDSincSampleGrabber<S, Sampler> {
+    pub fn new(sampler_reader: SampleReader<Sampler>, taps: SincTaps) -> Self {
+        SIMDSincSampleGrabber {
+            sampler_reader,
+            table: taps.table(),
+            _s: PhantomData,
+        }
+    }
+}
+
+impl<S: Simd, Sampler: BufferSampler> SIMDSampleGrabber<S> for SIMDSincSampleGrabber<S, Sampler> {
+    fn get(&self, indexes: S::Vi32, fractional: S::Vf32) -> S::Vf32 {
+        let table = self.table;
+        let first_tap = 1 - (table.taps / 2) as isize;
+
+        // Sized for the widest SIMD type
+        let mut phases = [0; 16];
+        for i in 0..S::VF32_WIDTH {
+            phases[i] = ((fractional[i] * SINC_PHASES as f32) as usize).min(SINC_PHASES - 1);
+        }
+
+        let mut sum = unsafe { S::set1_ps(0.0) };
+        for tap in 0..table.taps {
+            let mut values = unsafe { S::set1_ps(0.0) };
+            let mut coefficients = unsafe { S::set1_ps(0.0) };
+            for i in 0..S::VF32_WIDTH {
+                let index = indexes[i] as usize;
+                values[i] = self
+                    .sampler_reader
+                    .get_relative(index, first_tap + tap as isize);
+                coefficients[i] = table.coefficient(phases[i], tap);
+            }
+            sum += values * coefficients;
+        }
+
+        sum
+    }
+
+    fn is_past_end(&self, pos: f64) -> bool {
+        let pos = pos as usize;
+        self.sampler_reader.is_past_end(pos)
+    }
+
+    fn wrap_position(&self, pos: f64) -> f64 {
+        self.sampler_reader.wrap_position(pos)
+    }
+
+    fn signal_release(&mut self) {
+        self.sampler_reader.signal_release();
+    }
+}
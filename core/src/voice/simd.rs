@@ -167,6 +167,11 @@ where
         self.v1.process_controls(control);
         self.v2.process_controls(control);
     }
+
+    // Envelopes are applied by multiplication, so their levels multiply too
+    fn envelope_level(&self) -> f32 {
+        self.v1.envelope_level() * self.v2.envelope_level()
+    }
 }
 
 impl<T, TI, TO, V1, V2, F> SIMDVoiceGenerator<T, TO> for SIMDVoiceCombine<T, TI, TO, V1, V2, F>
@@ -183,6 +188,67 @@ where
     }
 }
 
+/// SIMD mono voice generator mapper, applying a function to each value
+pub struct SIMDVoiceMap<T, V, F>
+where
+    T: Simd,
+    V: SIMDVoiceGenerator<T, SIMDSampleMono<T>>,
+    F: Fn(f32) -> f32,
+{
+    v: V,
+    func: F,
+    _t: PhantomData<T>,
+}
+
+impl<T, V, F> SIMDVoiceMap<T, V, F>
+where
+    T: Simd,
+    V: SIMDVoiceGenerator<T, SIMDSampleMono<T>>,
+    F: Fn(f32) -> f32,
+{
+    pub fn new(v: V, func: F) -> Self {
+        SIMDVoiceMap {
+            v,
+            func,
+            _t: PhantomData,
+        }
+    }
+}
+
+impl<T, V, F> VoiceGeneratorBase for SIMDVoiceMap<T, V, F>
+where
+    T: Simd,
+    V: SIMDVoiceGenerator<T, SIMDSampleMono<T>>,
+    F: Sync + Send + Fn(f32) -> f32,
+{
+    fn ended(&self) -> bool {
+        self.v.ended()
+    }
+
+    fn signal_release(&mut self) {
+        self.v.signal_release();
+    }
+
+    fn process_controls(&mut self, control: &VoiceControlData) {
+        self.v.process_controls(control);
+    }
+}
+
+impl<T, V, F> SIMDVoiceGenerator<T, SIMDSampleMono<T>> for SIMDVoiceMap<T, V, F>
+where
+    T: Simd,
+    V: SIMDVoiceGenerator<T, SIMDSampleMono<T>>,
+    F: Sync + Send + Fn(f32) -> f32,
+{
+    fn next_sample(&mut self) -> SIMDSampleMono<T> {
+        let mut values = self.v.next_sample().0;
+        for i in 0..T::VF32_WIDTH {
+            values[i] = (self.func)(values[i]);
+        }
+        SIMDSampleMono(values)
+    }
+}
+
 /// Parent struct for base SIMD voice combination functions
 pub struct VoiceCombineSIMD<T: Simd>(PhantomData<T>);
 
@@ -226,6 +292,32 @@ impl<T: Simd> VoiceCombineSIMD<T> {
 
         SIMDVoiceCombine::new(voice1, voice2, add)
     }
+
+    /// Converts a generator outputting cents into a frequency multiplier
+    pub fn cents_to_multiplier<V>(voice: V) -> impl SIMDVoiceGenerator<T, SIMDSampleMono<T>>
+    where
+        V: SIMDVoiceGenerator<T, SIMDSampleMono<T>>,
+    {
+        #[inline(always)]
+        fn convert(cents: f32) -> f32 {
+            2.0f32.powf(cents / 1200.0)
+        }
+
+        SIMDVoiceMap::new(voice, convert)
+    }
+
+    /// Converts a generator outputting semitones into a frequency multiplier
+    pub fn semitones_to_multiplier<V>(voice: V) -> impl SIMDVoiceGenerator<T, SIMDSampleMono<T>>
+    where
+        V: SIMDVoiceGenerator<T, SIMDSampleMono<T>>,
+    {
+        #[inline(always)]
+        fn convert(semitones: f32) -> f32 {
+            2.0f32.powf(semitones / 12.0)
+        }
+
+        SIMDVoiceMap::new(voice, convert)
+    }
 }
 
 #[cfg(test)]
@@ -233,6 +325,7 @@ mod tests {
     use VoiceControlData;
 
     use super::*;
+    use crate::voice::SIMDConstant;
 
     use simdeez::*; // nuts
 
@@ -307,4 +400,27 @@ mod tests {
 
         run_runtime_select();
     }
+
+    #[test]
+    fn test_simd_voice_pitch_conversion() {
+        simd_runtime_generate!(
+            fn run() {
+                let cents = SIMDConstant::<S>::new(1200.0);
+                let semitones = SIMDConstant::<S>::new(-12.0);
+
+                let mut cents = VoiceCombineSIMD::<S>::cents_to_multiplier(cents);
+                let mut semitones = VoiceCombineSIMD::<S>::semitones_to_multiplier(semitones);
+
+                let cents = cents.next_sample();
+                let semitones = semitones.next_sample();
+
+                for i in 0..S::VF32_WIDTH {
+                    assert!((cents.0[i] - 2.0).abs() < 0.0001);
+                    assert!((semitones.0[i] - 0.5).abs() < 0.0001);
+                }
+            }
+        );
+
+        run_runtime_select();
+    }
 }
@@ -43,6 +43,10 @@ where
     fn process_controls(&mut self, control: &VoiceControlData) {
         self.generator.process_controls(control)
     }
+
+    fn envelope_level(&self) -> f32 {
+        self.generator.envelope_level()
+    }
 }
 
 impl<S, T> VoiceSampleGenerator for SIMDStereoVoice<S, T>
@@ -102,6 +106,10 @@ where
     fn process_controls(&mut self, control: &VoiceControlData) {
         self.generator.process_controls(control)
     }
+
+    fn envelope_level(&self) -> f32 {
+        self.generator.envelope_level()
+    }
 }
 
 impl<S, T> VoiceSampleGenerator for SIMDMonoVoice<S, T>
@@ -0,0 +1,96 @@
+use std::sync::Arc;
+
+/// Maps note velocities onto voice amplitudes.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub enum VelocityCurve {
+    /// An amplitude of 1.04^(vel - 127), about 0.34dB per velocity step
+    #[default]
+    Exponential,
+    /// An amplitude proportional to the velocity
+    Linear,
+    /// An amplitude of (vel / 127)^2, which is the default of both SF2 (its
+    /// concave velocity to attenuation modulator) and SFZ
+    Concave,
+    /// An amplitude looked up for each velocity
+    Custom(Arc<[f32; 128]>),
+}
+
+impl VelocityCurve {
+    /// Creates a custom curve from a lookup table of the amplitude at each velocity
+    pub fn custom(table: [f32; 128]) -> Self {
+        VelocityCurve::Custom(Arc::new(table))
+    }
+
+    /// Creates a custom curve through the given (velocity, amplitude) points, as
+    /// defined by the SFZ `amp_velcurve_N` opcodes. The velocities in between are
+    /// interpolated linearly, and the curve goes through (0, 0) and (127, 1)
+    /// unless those points are overridden. Later points override earlier ones
+    /// at the same velocity.
+    pub fn from_points(points: &[(u8, f32)]) -> Self {
+        let mut unique: Vec<(u8, f32)> = Vec::with_capacity(points.len() + 2);
+        for &(vel, gain) in points.iter().filter(|(vel, _)| *vel <= 127) {
+            match unique.iter_mut().find(|(other, _)| *other == vel) {
+                Some(point) => point.1 = gain,
+                None => unique.push((vel, gain)),
+            }
+        }
+        for default in [(0, 0.0), (127, 1.0)].iter() {
+            if !unique.iter().any(|(vel, _)| *vel == default.0) {
+                unique.push(*default);
+            }
+        }
+        unique.sort_by_key(|(vel, _)| *vel);
+
+        let mut table = [0.0; 128];
+        for pair in unique.windows(2) {
+            let (start_vel, start) = pair[0];
+            let (end_vel, end) = pair[1];
+            let length = (end_vel - start_vel) as f32;
+            for vel in start_vel..=end_vel {
+                let fac = (vel - start_vel) as f32 / length;
+                table[vel as usize] = start + (end - start) * fac;
+            }
+        }
+
+        VelocityCurve::custom(table)
+    }
+
+    /// The amplitude of a voice at the given velocity
+    pub fn gain(&self, vel: u8) -> f32 {
+        let vel = vel.min(127);
+        match self {
+            VelocityCurve::Exponential => 1.04f32.powf(vel as f32 - 127.0),
+            VelocityCurve::Linear => vel as f32 / 127.0,
+            VelocityCurve::Concave => (vel as f32 / 127.0).powi(2),
+            VelocityCurve::Custom(table) => table[vel as usize],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_velocity_curve() {
+        assert_eq!(VelocityCurve::Exponential.gain(127), 1.0);
+        assert_eq!(VelocityCurve::Linear.gain(0), 0.0);
+        assert_eq!(VelocityCurve::Concave.gain(127), 1.0);
+        assert!((VelocityCurve::Concave.gain(64) - 0.254).abs() < 0.001);
+
+        // The points are interpolated, starting from (0, 0) and ending at (127, 1)
+        let curve = VelocityCurve::from_points(&[(64, 0.8)]);
+        assert_eq!(curve.gain(0), 0.0);
+        assert_eq!(curve.gain(32), 0.4);
+        assert_eq!(curve.gain(64), 0.8);
+        assert_eq!(curve.gain(127), 1.0);
+
+        let curve = VelocityCurve::from_points(&[(0, 0.5), (127, 0.5)]);
+        assert_eq!(curve.gain(100), 0.5);
+
+        // Repeated velocities don't make empty segments
+        let curve = VelocityCurve::from_points(&[(64, 0.2), (64, 0.8)]);
+        assert_eq!(curve.gain(64), 0.8);
+        assert!((0..128).all(|vel| !curve.gain(vel).is_nan()));
+    }
+}
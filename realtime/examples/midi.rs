@@ -6,7 +6,7 @@ use std::{
 
 use core::{
     channel::{ChannelEvent, ControlEvent},
-    soundfont::{SoundfontBase, SquareSoundfont},
+    soundfont::{SampleSetOptions, SampleSetSoundfont, SoundfontBase},
 };
 use cpal::{traits::{DeviceTrait, HostTrait}};
 use midi_toolkit::{
@@ -31,15 +31,23 @@ fn main() {
     let config = device.default_output_config().unwrap();
     println!("Default output config: {:?}", config);
 
-    let synth = RealtimeSynth::open(16, &device, config);
+    let synth = RealtimeSynth::open(16, &device, config).unwrap();
     let mut sender = synth.get_senders();
 
     let params = synth.stream_params();
     
-    let soundfonts: Vec<Arc<dyn SoundfontBase>> = vec![Arc::new(SquareSoundfont::new(
+    let mut options = SampleSetOptions::new(
+        "D:/Midis/Steinway-B-211-master/Steinway-B-211-master/Samples",
+        "KEPSREC{key:3}.wav",
+    );
+    options.key_range = 0..=127;
+    options.root_keys = Some((21..=108).collect());
+
+    let soundfonts: Vec<Arc<dyn SoundfontBase>> = vec![Arc::new(SampleSetSoundfont::new(
+        &options,
         params.sample_rate,
         params.channels,
-    ))];
+    ).unwrap())];
 
     sender.send_event(SynthEvent::AllChannels(ChannelEvent::SetSoundfonts(
         soundfonts,
@@ -0,0 +1,70 @@
+use std::{error::Error, fmt};
+
+use cpal::{BuildStreamError, DefaultStreamConfigError, PlayStreamError};
+use rayon::ThreadPoolBuildError;
+
+/// An error encountered while opening the realtime synth's audio output.
+#[derive(Debug)]
+pub enum SynthOpenError {
+    /// The host has no default output device
+    NoOutputDevice,
+
+    /// The output device's default config couldn't be queried
+    DefaultConfig {
+        device: String,
+        error: DefaultStreamConfigError,
+    },
+
+    /// The output stream couldn't be created on the device
+    BuildStream {
+        device: String,
+        error: BuildStreamError,
+    },
+
+    /// The output stream couldn't be started
+    PlayStream {
+        device: String,
+        error: PlayStreamError,
+    },
+
+    /// The render thread pool couldn't be created
+    ThreadPool(ThreadPoolBuildError),
+}
+
+impl fmt::Display for SynthOpenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SynthOpenError::NoOutputDevice => write!(f, "no output device found"),
+            SynthOpenError::DefaultConfig { device, error } => write!(
+                f,
+                "failed to get the default config of output device {}: {}",
+                device, error
+            ),
+            SynthOpenError::BuildStream { device, error } => write!(
+                f,
+                "failed to build the output stream on device {}: {}",
+                device, error
+            ),
+            SynthOpenError::PlayStream { device, error } => write!(
+                f,
+                "failed to start the output stream on device {}: {}",
+                device, error
+            ),
+            SynthOpenError::ThreadPool(error) => {
+                write!(f, "failed to create the render thread pool: {}", error)
+            }
+        }
+    }
+}
+
+impl Error for SynthOpenError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SynthOpenError::NoOutputDevice => None,
+            SynthOpenError::DefaultConfig { error, .. } => Some(error),
+            SynthOpenError::BuildStream { error, .. } => Some(error),
+            SynthOpenError::PlayStream { error, .. } => Some(error),
+            SynthOpenError::ThreadPool(error) => Some(error),
+        }
+    }
+}
@@ -1,3 +1,6 @@
+mod error;
+pub use error::*;
+
 mod event;
 pub use event::*;
 
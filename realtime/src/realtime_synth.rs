@@ -11,19 +11,20 @@ use std::{
 
 use cpal::{
     traits::{DeviceTrait, HostTrait, StreamTrait},
-    Device, PauseStreamError, PlayStreamError, Sample, Stream, SupportedStreamConfig,
+    BuildStreamError, Device, PauseStreamError, PlayStreamError, Sample, Stream,
+    SupportedStreamConfig,
 };
 use crossbeam_channel::{bounded, unbounded, Sender};
 use to_vec::ToVec;
 
 use core::{
-    channel::{ChannelEvent, ControlEvent, VoiceChannel},
+    channel::{parse_drum_part_sysex, ChannelEvent, ControlEvent, VoiceChannel},
     effects::VolumeLimiter,
     helpers::{prepapre_cache_vec, sum_simd},
     AudioPipe, AudioStreamParams, BufferedRenderer, BufferedRendererStatsReader, FunctionAudioPipe,
 };
 
-use crate::SynthEvent;
+use crate::{SynthEvent, SynthOpenError};
 
 struct ReadWriteAtomicU64(UnsafeCell<u64>);
 
@@ -233,6 +234,37 @@ impl RealtimeEventSender {
         }
     }
 
+    /// Releases every note, silences every channel immediately and resets their
+    /// controllers, so that no note or pedal stays stuck
+    pub fn reset_synth(&mut self) {
+        self.send_event(SynthEvent::AllChannels(ChannelEvent::AllNotesOff));
+        self.send_event(SynthEvent::AllChannels(ChannelEvent::AllNotesKilled));
+        self.send_event(SynthEvent::AllChannels(ChannelEvent::Control(
+            ControlEvent::Raw(0x79, 0),
+        )));
+    }
+
+    /// Sends a system exclusive message, with or without its 0xF0 and 0xF7 bytes.
+    /// GS and XG drum part messages switch the percussion mode of the channel
+    /// they address, and other messages are sent to every channel.
+    pub fn send_sysex(&mut self, message: &[u8]) {
+        match parse_drum_part_sysex(message) {
+            Some((channel, percussion)) => {
+                if (channel as usize) < self.senders.len() {
+                    self.send_event(SynthEvent::Channel(
+                        channel as u32,
+                        ChannelEvent::SetPercussionMode(percussion),
+                    ));
+                }
+            }
+            None => {
+                self.send_event(SynthEvent::AllChannels(ChannelEvent::SysEx(
+                    message.to_vec(),
+                )));
+            }
+        }
+    }
+
     pub fn send_event_u32(&mut self, event: u32) {
         let head = event & 0xFF;
         let channel = head & 0xF;
@@ -266,12 +298,34 @@ impl RealtimeEventSender {
                     },
                 ));
             }
+            0xA => {
+                self.send_event(SynthEvent::Channel(
+                    channel,
+                    ChannelEvent::PolyAftertouch {
+                        key: val1!(),
+                        value: val2!() as f32 / 127.0,
+                    },
+                ));
+            }
             0xB => {
                 self.send_event(SynthEvent::Channel(
                     channel,
                     ChannelEvent::Control(ControlEvent::Raw(val1!(), val2!())),
                 ));
             }
+            0xC => {
+                self.send_event(SynthEvent::Channel(
+                    channel,
+                    ChannelEvent::ProgramChange(val1!()),
+                ));
+            }
+            0xD => {
+                let pressure = val1!() as f32 / 127.0;
+                self.send_event(SynthEvent::Channel(
+                    channel,
+                    ChannelEvent::Control(ControlEvent::ChannelPressure(pressure)),
+                ));
+            }
             0xE => {
                 let value = (((val2!() as i16) << 7) | val1!() as i16) - 8192;
                 let value = value as f32 / 8192.0;
@@ -339,20 +393,30 @@ pub struct RealtimeSynth {
 }
 
 impl RealtimeSynth {
-    pub fn open_with_default_output(channel_count: u32) -> Self {
+    pub fn open_with_default_output(channel_count: u32) -> Result<Self, SynthOpenError> {
         let host = cpal::default_host();
 
         let device = host
             .default_output_device()
-            .expect("failed to find output device");
-        println!("Output device: {}", device.name().unwrap());
+            .ok_or(SynthOpenError::NoOutputDevice)?;
+        println!("Output device: {}", device_name(&device));
 
-        let config = device.default_output_config().unwrap();
+        let config =
+            device
+                .default_output_config()
+                .map_err(|error| SynthOpenError::DefaultConfig {
+                    device: device_name(&device),
+                    error,
+                })?;
 
         RealtimeSynth::open(channel_count, &device, config)
     }
 
-    pub fn open(channel_count: u32, device: &Device, config: SupportedStreamConfig) -> Self {
+    pub fn open(
+        channel_count: u32,
+        device: &Device,
+        config: SupportedStreamConfig,
+    ) -> Result<Self, SynthOpenError> {
         let mut channels = Vec::new();
         let mut senders = Vec::new();
         let mut command_senders = Vec::new();
@@ -363,15 +427,22 @@ impl RealtimeSynth {
         let use_threadpool = false;
 
         let pool = if use_threadpool {
-            Some(Arc::new(rayon::ThreadPoolBuilder::new().build().unwrap()))
+            let pool = rayon::ThreadPoolBuilder::new()
+                .build()
+                .map_err(SynthOpenError::ThreadPool)?;
+            Some(Arc::new(pool))
         } else {
             None
         };
 
         let (output_sender, output_receiver) = bounded::<Vec<f32>>(channel_count as usize);
 
-        for _ in 0u32..channel_count {
+        for i in 0u32..channel_count {
             let mut channel = VoiceChannel::new(sample_rate, audio_channels, pool.clone());
+            // General MIDI plays drums on channel 10
+            if i == 9 {
+                channel.process_event(ChannelEvent::SetPercussionMode(true));
+            }
             channels.push(channel.clone());
             let (event_sender, event_receiver) = unbounded();
             senders.push(event_sender);
@@ -433,45 +504,48 @@ impl RealtimeSynth {
             device: &Device,
             config: SupportedStreamConfig,
             buffered: Arc<Mutex<BufferedRenderer>>,
-        ) -> Stream {
+        ) -> Result<Stream, BuildStreamError> {
             let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
             let mut output_vec = Vec::new();
 
             let mut limiter = VolumeLimiter::new(config.channels());
 
-            let stream = device
-                .build_output_stream(
-                    &config.into(),
-                    move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
-                        output_vec.reserve(data.len());
-                        for _ in 0..data.len() {
-                            output_vec.push(0.0);
-                        }
-                        buffered.lock().unwrap().read(&mut output_vec);
-                        let mut i = 0;
-                        for s in limiter.limit_iter(output_vec.drain(0..)) {
-                            data[i] = Sample::from(&s);
-                            i += 1;
-                        }
-                    },
-                    err_fn,
-                )
-                .unwrap();
-
-            stream
+            device.build_output_stream(
+                &config.into(),
+                move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                    output_vec.reserve(data.len());
+                    for _ in 0..data.len() {
+                        output_vec.push(0.0);
+                    }
+                    buffered.lock().unwrap().read(&mut output_vec);
+                    let mut i = 0;
+                    for s in limiter.limit_iter(output_vec.drain(0..)) {
+                        data[i] = Sample::from(&s);
+                        i += 1;
+                    }
+                },
+                err_fn,
+            )
         }
 
         let stream = match config.sample_format() {
             cpal::SampleFormat::F32 => build_stream::<f32>(&device, config, buffered.clone()),
             cpal::SampleFormat::I16 => build_stream::<i16>(&device, config, buffered.clone()),
             cpal::SampleFormat::U16 => build_stream::<u16>(&device, config, buffered.clone()),
-        };
+        }
+        .map_err(|error| SynthOpenError::BuildStream {
+            device: device_name(device),
+            error,
+        })?;
 
-        stream.play().unwrap();
+        stream.play().map_err(|error| SynthOpenError::PlayStream {
+            device: device_name(device),
+            error,
+        })?;
 
         let max_nps = Arc::new(ReadWriteAtomicU64::new(10000));
 
-        Self {
+        Ok(Self {
             _channels: channels,
             buffered_renderer: buffered,
 
@@ -479,7 +553,7 @@ impl RealtimeSynth {
             stream,
             stats,
             stream_params: AudioStreamParams::new(sample_rate, audio_channels),
-        }
+        })
     }
 
     pub fn send_event(&mut self, event: SynthEvent) {
@@ -508,3 +582,7 @@ impl RealtimeSynth {
         self.stream.play()
     }
 }
+
+fn device_name(device: &Device) -> String {
+    device.name().unwrap_or_else(|_| "<unknown>".to_string())
+}
@@ -0,0 +1,34 @@
+#[derive(Debug, Clone)]
+pub struct XSynthRenderConfig {
+    /// The amount of MIDI channels to render
+    pub channel_count: u32,
+
+    /// The sample rate of the output file
+    pub sample_rate: u32,
+
+    /// The amount of audio channels in the output file
+    pub audio_channels: u16,
+
+    /// Render each MIDI channel on a separate thread
+    pub use_threadpool: bool,
+
+    /// Apply the volume limiter to the output, to avoid clipping
+    pub use_limiter: bool,
+
+    /// The maximum amount of seconds to keep rendering after the last event,
+    /// while voices are still releasing
+    pub max_tail: f64,
+}
+
+impl Default for XSynthRenderConfig {
+    fn default() -> Self {
+        XSynthRenderConfig {
+            channel_count: 16,
+            sample_rate: 48000,
+            audio_channels: 2,
+            use_threadpool: true,
+            use_limiter: true,
+            max_tail: 10.0,
+        }
+    }
+}
@@ -0,0 +1,6 @@
+use core::channel::ChannelEvent;
+
+pub enum SynthEvent {
+    Channel(u32, ChannelEvent),
+    AllChannels(ChannelEvent),
+}
@@ -0,0 +1,14 @@
+mod config;
+pub use config::*;
+
+mod event;
+pub use event::*;
+
+mod writer;
+pub use writer::*;
+
+mod renderer;
+pub use renderer::*;
+
+mod midi;
+pub use midi::*;
@@ -0,0 +1,51 @@
+use std::{path::Path, sync::Arc, time::Instant};
+
+use core::soundfont::{LoadSfError, Sf2Soundfont, SfzSoundfont, SoundfontBase};
+use xsynth_render::{render_midi, XSynthRenderConfig};
+
+fn load_soundfont(
+    path: &Path,
+    config: &XSynthRenderConfig,
+) -> Result<Arc<dyn SoundfontBase>, LoadSfError> {
+    let (sample_rate, channels) = (config.sample_rate, config.audio_channels);
+
+    Ok(match path.extension().and_then(|ext| ext.to_str()) {
+        Some("sf2") => Arc::new(Sf2Soundfont::new_all_presets(path, sample_rate, channels)?),
+        Some("sfz") => Arc::new(SfzSoundfont::new(path, sample_rate, channels)?),
+        _ => {
+            return Err(LoadSfError::Unsupported {
+                path: path.into(),
+                reason: "unknown soundfont format".to_string(),
+            })
+        }
+    })
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 4 {
+        println!("Usage: xsynth-render <midi> <output wav> <soundfonts...>");
+        return;
+    }
+
+    let config = XSynthRenderConfig::default();
+
+    let soundfonts = args[3..]
+        .iter()
+        .map(|path| load_soundfont(Path::new(path), &config))
+        .collect::<Result<_, _>>();
+    let soundfonts = match soundfonts {
+        Ok(soundfonts) => soundfonts,
+        Err(e) => {
+            eprintln!("Failed to load soundfont: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let start = Instant::now();
+    if let Err(e) = render_midi(&args[1], soundfonts, Path::new(&args[2]), config) {
+        eprintln!("Failed to render: {}", e);
+        std::process::exit(1);
+    }
+    println!("Rendered in {:.2}s", start.elapsed().as_secs_f64());
+}
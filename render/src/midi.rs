@@ -0,0 +1,108 @@
+use std::{io, path::Path, sync::Arc};
+
+use core::{
+    channel::{parse_drum_part_sysex, ChannelEvent, ControlEvent},
+    soundfont::SoundfontBase,
+};
+
+use midi_toolkit::{
+    events::{Event, MIDIEvent},
+    io::MIDIFile,
+    pipe,
+    sequence::{
+        event::{cancel_tempo_events, merge_events_array, scale_event_time},
+        to_vec, unwrap_items, TimeCaster,
+    },
+};
+
+use crate::{SynthEvent, XSynthRender, XSynthRenderConfig};
+
+/// Converts a MIDI event into its synth event, if the synth handles it
+fn convert_event(event: &Event<f64>) -> Option<SynthEvent> {
+    match event {
+        Event::NoteOn(e) => Some(SynthEvent::Channel(
+            e.channel as u32,
+            ChannelEvent::NoteOn {
+                key: e.key,
+                vel: e.velocity,
+            },
+        )),
+        Event::NoteOff(e) => Some(SynthEvent::Channel(
+            e.channel as u32,
+            ChannelEvent::NoteOff { key: e.key },
+        )),
+        Event::PolyphonicKeyPressure(e) => Some(SynthEvent::Channel(
+            e.channel as u32,
+            ChannelEvent::PolyAftertouch {
+                key: e.key,
+                value: e.velocity as f32 / 127.0,
+            },
+        )),
+        Event::ControlChange(e) => Some(SynthEvent::Channel(
+            e.channel as u32,
+            ChannelEvent::Control(ControlEvent::Raw(e.controller, e.value)),
+        )),
+        Event::ProgramChange(e) => Some(SynthEvent::Channel(
+            e.channel as u32,
+            ChannelEvent::ProgramChange(e.program),
+        )),
+        Event::ChannelPressure(e) => Some(SynthEvent::Channel(
+            e.channel as u32,
+            ChannelEvent::Control(ControlEvent::ChannelPressure(e.pressure as f32 / 127.0)),
+        )),
+        Event::PitchWheelChange(e) => Some(SynthEvent::Channel(
+            e.channel as u32,
+            ChannelEvent::Control(ControlEvent::PitchBendValue(e.pitch as f32 / 8192.0)),
+        )),
+        // Drum part messages address a single channel, and other messages apply to all of them
+        Event::SystemExclusiveMessage(e) => match parse_drum_part_sysex(&e.data) {
+            Some((channel, percussion)) => Some(SynthEvent::Channel(
+                channel as u32,
+                ChannelEvent::SetPercussionMode(percussion),
+            )),
+            None => Some(SynthEvent::AllChannels(ChannelEvent::SysEx(e.data.clone()))),
+        },
+        _ => None,
+    }
+}
+
+/// Renders a MIDI file into a wav file, using the given soundfonts on all channels
+pub fn render_midi(
+    midi_path: &str,
+    soundfonts: Vec<Arc<dyn SoundfontBase>>,
+    out_path: &Path,
+    config: XSynthRenderConfig,
+) -> io::Result<()> {
+    let midi = MIDIFile::open(midi_path, None)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))?;
+
+    let mut render = XSynthRender::new(config, out_path)?;
+    render.send_event(SynthEvent::AllChannels(ChannelEvent::SetSoundfonts(
+        soundfonts,
+    )));
+
+    let ppq = midi.ppq();
+    let merged = pipe!(
+        midi.iter_all_tracks()
+        |>to_vec()
+        |>merge_events_array()
+        |>TimeCaster::<f64>::cast_event_delta()
+        |>cancel_tempo_events(250000)
+        |>scale_event_time(1.0 / ppq as f64)
+        |>unwrap_items()
+    );
+
+    let mut time = 0.0;
+    for e in merged {
+        if e.delta() != 0.0 {
+            time += e.delta();
+            render.render_until(time)?;
+        }
+
+        if let Some(event) = convert_event(&e) {
+            render.send_event(event);
+        }
+    }
+
+    render.finalize()
+}
@@ -0,0 +1,171 @@
+use std::{io, path::Path};
+
+use core::{
+    channel::{ChannelEvent, VoiceChannel},
+    effects::VolumeLimiter,
+    helpers::{prepapre_cache_vec, sum_simd},
+    AudioPipe, AudioStreamParams,
+};
+
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
+
+use crate::{SynthEvent, WavWriter, XSynthRenderConfig};
+
+/// The amount of frames rendered at once, events within a block are applied at their offset
+const BLOCK_FRAMES: u64 = 4096;
+
+/// Renders the synth output into a wav file as fast as possible, without a
+/// realtime clock. Events are applied at the exact sample they are sent at.
+pub struct XSynthRender {
+    config: XSynthRenderConfig,
+    stream_params: AudioStreamParams,
+
+    channels: Vec<VoiceChannel>,
+    channel_buffers: Vec<Vec<f32>>,
+    output_buffer: Vec<f32>,
+
+    limiter: Option<VolumeLimiter>,
+    writer: WavWriter,
+
+    /// The amount of frames rendered so far
+    rendered_frames: u64,
+    /// The frame that events are currently sent at, which may be ahead of the rendered frames
+    position: u64,
+}
+
+impl XSynthRender {
+    pub fn new(config: XSynthRenderConfig, out_path: &Path) -> io::Result<Self> {
+        let channels = (0..config.channel_count)
+            .map(|i| {
+                let channel = VoiceChannel::new(config.sample_rate, config.audio_channels, None);
+                // General MIDI plays drums on channel 10
+                if i == 9 {
+                    channel.process_event(ChannelEvent::SetPercussionMode(true));
+                }
+                channel
+            })
+            .collect();
+        let channel_buffers = (0..config.channel_count).map(|_| Vec::new()).collect();
+
+        let limiter = if config.use_limiter {
+            Some(VolumeLimiter::new(config.audio_channels))
+        } else {
+            None
+        };
+
+        let writer = WavWriter::new(out_path, config.sample_rate, config.audio_channels)?;
+
+        Ok(XSynthRender {
+            stream_params: AudioStreamParams::new(config.sample_rate, config.audio_channels),
+            config,
+            channels,
+            channel_buffers,
+            output_buffer: Vec::new(),
+            limiter,
+            writer,
+            rendered_frames: 0,
+            position: 0,
+        })
+    }
+
+    pub fn stream_params(&self) -> &AudioStreamParams {
+        &self.stream_params
+    }
+
+    /// Applies an event at the current render position. Events for channels
+    /// past the configured channel count are ignored.
+    pub fn send_event(&mut self, event: SynthEvent) {
+        let offset = (self.position - self.rendered_frames) as u32;
+        match event {
+            SynthEvent::Channel(channel, event) => {
+                if let Some(channel) = self.channels.get(channel as usize) {
+                    channel.push_timed_events_iter(std::iter::once((offset, event)));
+                }
+            }
+            SynthEvent::AllChannels(event) => {
+                for channel in self.channels.iter() {
+                    channel.push_timed_events_iter(std::iter::once((offset, event.clone())));
+                }
+            }
+        }
+    }
+
+    /// Moves the render position to the time in seconds, so that the next
+    /// events sent land on the sample closest to that time. Audio is rendered
+    /// in fixed size blocks, so the output doesn't depend on the event timing.
+    pub fn render_until(&mut self, time: f64) -> io::Result<()> {
+        let target_frame = (time * self.config.sample_rate as f64).round() as u64;
+
+        while target_frame >= self.rendered_frames + BLOCK_FRAMES {
+            self.render_frames(BLOCK_FRAMES as usize)?;
+        }
+        self.position = self.position.max(target_frame);
+
+        Ok(())
+    }
+
+    /// Keeps rendering while any voices are still playing, up to the configured
+    /// maximum tail length, then finishes writing the file.
+    pub fn finalize(mut self) -> io::Result<()> {
+        let max_tail_frames = (self.config.max_tail * self.config.sample_rate as f64) as u64;
+
+        // Events sent at the end of the render are applied at the start of the tail
+        if self.position > self.rendered_frames {
+            self.render_frames((self.position - self.rendered_frames) as usize)?;
+        }
+
+        let mut tail_frames = 0;
+        while self.voice_count() > 0 && tail_frames < max_tail_frames {
+            let frames = (max_tail_frames - tail_frames).min(BLOCK_FRAMES);
+            self.render_frames(frames as usize)?;
+            tail_frames += frames;
+        }
+
+        self.writer.finalize()
+    }
+
+    pub fn voice_count(&self) -> u64 {
+        self.channels
+            .iter()
+            .map(|c| c.get_channel_stats().voice_count())
+            .sum()
+    }
+
+    fn render_frames(&mut self, frames: usize) -> io::Result<()> {
+        let len = frames * self.config.audio_channels as usize;
+
+        fn render_channel(channel: &mut VoiceChannel, buffer: &mut Vec<f32>, len: usize) {
+            prepapre_cache_vec(buffer, len, 0.0);
+            channel.read_samples(buffer);
+        }
+
+        if self.config.use_threadpool {
+            self.channels
+                .par_iter_mut()
+                .zip(self.channel_buffers.par_iter_mut())
+                .for_each(|(channel, buffer)| render_channel(channel, buffer, len));
+        } else {
+            for (channel, buffer) in self
+                .channels
+                .iter_mut()
+                .zip(self.channel_buffers.iter_mut())
+            {
+                render_channel(channel, buffer, len);
+            }
+        }
+
+        prepapre_cache_vec(&mut self.output_buffer, len, 0.0);
+        for buffer in self.channel_buffers.iter() {
+            sum_simd(buffer, &mut self.output_buffer);
+        }
+
+        if let Some(limiter) = self.limiter.as_mut() {
+            limiter.limit(&mut self.output_buffer);
+        }
+
+        self.writer.write_samples(&self.output_buffer)?;
+        self.rendered_frames += frames as u64;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+const WAV_FORMAT_IEEE_FLOAT: u16 = 3;
+const HEADER_SIZE: u32 = 44;
+
+/// Streams 32 bit float samples into a wav file, so that long renders
+/// don't have to be kept in memory.
+pub struct WavWriter {
+    writer: BufWriter<File>,
+    sample_rate: u32,
+    channels: u16,
+    samples_written: u64,
+}
+
+impl WavWriter {
+    pub fn new(path: &Path, sample_rate: u32, channels: u16) -> io::Result<WavWriter> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        write_header(&mut writer, sample_rate, channels, 0)?;
+
+        Ok(WavWriter {
+            writer,
+            sample_rate,
+            channels,
+            samples_written: 0,
+        })
+    }
+
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        for sample in samples {
+            self.writer.write_all(&sample.to_le_bytes())?;
+        }
+        self.samples_written += samples.len() as u64;
+        Ok(())
+    }
+
+    /// Fills in the chunk sizes now that the length is known, and flushes the file
+    pub fn finalize(mut self) -> io::Result<()> {
+        let data_size = (self.samples_written * 4).min(u32::MAX as u64 - HEADER_SIZE as u64);
+
+        self.writer.seek(SeekFrom::Start(0))?;
+        write_header(
+            &mut self.writer,
+            self.sample_rate,
+            self.channels,
+            data_size as u32,
+        )?;
+        self.writer.flush()
+    }
+}
+
+fn write_header(
+    writer: &mut impl Write,
+    sample_rate: u32,
+    channels: u16,
+    data_size: u32,
+) -> io::Result<()> {
+    let bits_per_sample = 32u16;
+    let block_align = channels * bits_per_sample / 8;
+    let byte_rate = sample_rate * block_align as u32;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    writer.write_all(&WAV_FORMAT_IEEE_FLOAT.to_le_bytes())?;
+    writer.write_all(&channels.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&byte_rate.to_le_bytes())?;
+    writer.write_all(&block_align.to_le_bytes())?;
+    writer.write_all(&bits_per_sample.to_le_bytes())?;
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())?;
+
+    Ok(())
+}